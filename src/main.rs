@@ -1,12 +1,43 @@
 mod utils;
 
-use actix_web::{ get, web, App, HttpRequest, HttpResponse, HttpServer, Responder };
+use actix_web::{
+    delete,
+    dev::{ Service, ServiceResponse },
+    get,
+    http::header::{ HeaderName, HeaderValue },
+    put,
+    routes,
+    web,
+    App,
+    HttpRequest,
+    HttpResponse,
+    HttpMessage,
+    HttpServer,
+    Responder,
+};
 use clap::{ error::ErrorKind, CommandFactory, Parser };
 use lazy_static::lazy_static;
 use local_ip_address::local_ip;
 use serde_urlencoded::from_str;
 use serde_yaml::Value as YamlValue;
-use utils::{ build, config, qrcode };
+use std::{ collections::HashMap, sync::Arc, time::Instant };
+use tracing::Instrument;
+use utils::{
+    access_log::{ self, RequestTarget },
+    auth::{ self, AccessToken },
+    build,
+    config,
+    convert,
+    error::AppError,
+    https::{ self, CertResolver },
+    metrics,
+    overrides,
+    profile,
+    qrcode,
+    server_config::{ self, ServerConfig },
+    singbox,
+    template,
+};
 
 const SPECIFICATION: &str = include_str!("../使用说明.txt");
 
@@ -18,9 +49,41 @@ struct Args {
     #[arg(short, long, default_value = "10111")]
     port: u16,
 
-    /// 默认转换为v2ray，可选singbox、clash
+    /// 默认转换为v2ray，可选auto（根据User-Agent选择）、singbox、xray、clash、clash-provider、surge、surfboard、quanx、loon
     #[arg(long, default_value = "v2ray")]
     target: String,
+
+    /// v2ray订阅的编码方式，可选base64、base64url、raw（不编码）
+    #[arg(long, default_value = "base64")]
+    encode: String,
+
+    /// 非TLS的端口池（逗号分隔）
+    #[arg(long, value_delimiter = ',', default_value = "80,8080,8880,2052,2082,2086,2095")]
+    http_ports: Vec<u16>,
+
+    /// TLS的端口池（逗号分隔）
+    #[arg(long, value_delimiter = ',', default_value = "443,2053,2083,2087,2096,8443")]
+    https_ports: Vec<u16>,
+
+    /// 服务器配置文件（远程模板的允许主机等），每次请求时读取
+    #[arg(long, default_value = "server.yaml")]
+    server_config: String,
+
+    /// 监听的地址（逗号分隔，可以多个），例如0.0.0.0:10111、[::]:10111、unix:/run/cfwks.sock，默认为0.0.0.0:<port>
+    #[arg(long, value_delimiter = ',')]
+    bind: Vec<String>,
+
+    /// HTTPS的证书文件（PEM），跟--tls-key一起设置时，TCP地址使用HTTPS，收到SIGHUP信号时重新加载
+    #[arg(long)]
+    tls_cert: Option<String>,
+
+    /// HTTPS的私钥文件（PEM）
+    #[arg(long)]
+    tls_key: Option<String>,
+
+    /// 将HTTP请求重定向到HTTPS的监听地址（逗号分隔，可以多个），例如0.0.0.0:80，需要启用HTTPS
+    #[arg(long, value_delimiter = ',')]
+    redirect_bind: Vec<String>,
 }
 
 // 共享Args结构体中的数据状态（让Args在其它地方使用）
@@ -36,30 +99,138 @@ pub struct Params {
     pub userid: u8,
     pub column_name: String,
     pub template: bool,
+    pub template_name: String,
+    pub minimal: bool,
     pub proxy_type: String,
     pub tls_mode: String,
     pub data_source: String,
     pub page: usize,
+    pub http_ports: Vec<u16>,
+    pub https_ports: Vec<u16>,
+    pub port_mode: String,
+    pub fixed_port: u16,
+    pub expand_ports: bool,
+    pub seed: Option<u64>,
+    pub pairing: String,
+    pub node_cap: usize,
+    pub encode: String,
+    pub singbox_version: Option<u32>,
+    pub placement: String,
+    pub group_by: String,
+    pub overrides: Vec<(String, String)>,
+    pub allowed_ids: Vec<usize>,
+    pub allowed_names: Vec<String>,
+    pub node_limit: usize,
 }
 
 lazy_static! {
     static ref CONFIG_FILE: &'static str = "config.yaml";
-    static ref CLASH_TEMPLATE: &'static str = "template/clash.yaml";
-    static ref SINGBOX_TEMPLATE: &'static str = "template/sing-box.json";
+    // 各个转换目标使用的模板文件
+    static ref TEMPLATES: HashMap<&'static str, &'static str> = HashMap::from([
+        ("clash", "template/clash.yaml"),
+        ("singbox", "template/sing-box.json"),
+        ("xray", "template/xray.json"),
+        ("surge", "template/surge.conf"),
+        ("surfboard", "template/surfboard.conf"),
+        ("quanx", "template/quanx.conf"),
+        ("loon", "template/loon.conf"),
+    ]);
 }
 
 async fn default_route() -> impl Responder {
     HttpResponse::NotFound().body("Not found.")
 }
 
+// Prometheus格式的指标，设置了auth.admin_token时，需要使用请求头 Authorization: Bearer <admin_token>
+#[get("/metrics")]
+async fn metrics_export(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let server_config = ServerConfig::load(&data.args.server_config);
+    if !server_config.admin_token.is_empty() {
+        authenticate_admin(&req, &server_config)?;
+    }
+    Ok(HttpResponse::Ok().content_type("text/plain; version=0.0.4; charset=utf-8").body(metrics::gather()))
+}
+
+// 输出一个请求的访问日志（路径和参数中的token等已隐藏），记录请求数的指标，并在响应头中返回请求ID
+fn log_request<B>(response: &mut ServiceResponse<B>, request_id: &str, started: Instant) {
+    let request = response.request();
+    let route = request.match_pattern().unwrap_or("unmatched".to_string());
+    let target = request
+        .extensions()
+        .get::<RequestTarget>()
+        .map(|target| target.0.clone())
+        .unwrap_or_default();
+    let target = access_log::target_label(&target);
+    let path = access_log::redact_path(request.path(), request.match_info().get("token").is_some());
+    let query = access_log::redact_query(request.query_string());
+    let status = response.status().as_u16();
+    let error = response
+        .response()
+        .error()
+        .map(|e| e.to_string());
+
+    metrics::record_request(&route, target, status);
+    tracing::info!(
+        method = %request.method(),
+        path = %path,
+        query = %query,
+        status,
+        target = %target,
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        peer = %request.connection_info().realip_remote_addr().unwrap_or("-"),
+        error = error.as_deref(),
+        "access"
+    );
+
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        response.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+    }
+}
+
+// 将HTTP请求重定向到HTTPS（主机不变，端口改为HTTPS监听的端口）
+async fn redirect_to_https(req: HttpRequest, https_port: web::Data<u16>) -> impl Responder {
+    let host = req.connection_info().host().to_owned();
+    // 去掉Host中的端口，IPv6地址保留方括号
+    let hostname = match host.rfind(':') {
+        Some(pos) if !host[pos..].contains(']') => &host[..pos],
+        _ => &host,
+    };
+    let location = match **https_port {
+        443 => format!("https://{}{}", hostname, req.uri()),
+        port => format!("https://{}:{}{}", hostname, port, req.uri()),
+    };
+    HttpResponse::PermanentRedirect().insert_header(("Location", location)).finish()
+}
+
+// 收到SIGHUP信号时重新加载HTTPS证书（例如证书续期后执行 kill -HUP <pid>）
+#[cfg(unix)]
+fn reload_cert_on_sighup(resolver: Arc<CertResolver>) {
+    use tokio::signal::unix::{ signal, SignalKind };
+    actix_web::rt::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                eprintln!("无法监听SIGHUP信号：{}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            match resolver.reload() {
+                Ok(()) => println!("已重新加载HTTPS证书"),
+                Err(e) => eprintln!("重新加载HTTPS证书失败：{}，继续使用原来的证书", e),
+            }
+        }
+    });
+}
+
 #[get("/")]
 async fn index(req: HttpRequest) -> impl Responder {
     let host_address = req.connection_info().host().to_owned();
 
     let html_body = SPECIFICATION.replace("127.0.0.1:10111", &host_address);
 
-    // 获取当前局域网IP地址
-    let ip_address = local_ip().unwrap().to_string();
+    // 获取当前局域网IP地址，获取失败就保留127.0.0.1
+    let ip_address = local_ip().map_or("127.0.0.1".to_string(), |ip| ip.to_string());
 
     // 获取当前URL
     let url = format!(
@@ -75,10 +246,301 @@ async fn index(req: HttpRequest) -> impl Responder {
     HttpResponse::Ok().content_type("text/html; charset=utf-8").body(html_content)
 }
 
+#[routes]
 #[get("/sub")]
-async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+#[get("/sub/{token}")]
+async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    subscribe(&req, &data, None, None)
+}
+
+// Clash/Mihomo的proxy-providers订阅，只返回proxies列表，参数跟/sub一样（target参数无效）
+#[routes]
+#[get("/provider")]
+#[get("/provider/{token}")]
+async fn provider(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    subscribe(&req, &data, Some("clash-provider"), None)
+}
+
+// 保存的订阅配置（服务器配置的profiles或者通过管理接口保存），链接中的参数会覆盖配置中的同名参数
+#[routes]
+#[get("/s/{profile}")]
+#[get("/s/{profile}/{token}")]
+async fn saved_profile(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let name = req.match_info().get("profile").unwrap_or_default().to_string();
+    subscribe(&req, &data, None, Some(&name))
+}
+
+// 管理接口：列出所有的订阅配置
+#[get("/admin/profiles")]
+async fn admin_list_profiles(
+    req: HttpRequest,
+    data: web::Data<AppState>
+) -> Result<HttpResponse, AppError> {
+    let server_config = ServerConfig::load(&data.args.server_config);
+    authenticate_admin(&req, &server_config)?;
+
+    let profiles: Vec<serde_json::Value> = profile
+        ::list_profiles(&server_config)
+        .into_iter()
+        .map(|p| {
+            let params: serde_json::Map<String, serde_json::Value> = p.params
+                .into_iter()
+                .map(|(key, value)| (key, serde_json::Value::from(value)))
+                .collect();
+            serde_json::json!({
+                "name": p.name,
+                "url": format!("/s/{}", p.name),
+                "editable": p.editable,
+                "params": params,
+            })
+        })
+        .collect();
+    let body = serde_json::to_string_pretty(&profiles).unwrap_or_default();
+
+    Ok(HttpResponse::Ok().content_type("application/json; charset=utf-8").body(body))
+}
+
+// 管理接口：新建或修改订阅配置，请求内容为JSON对象，例如 {"target": "clash", "id": 2, "n": 80}
+#[put("/admin/profiles/{profile}")]
+async fn admin_save_profile(
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState>
+) -> Result<HttpResponse, AppError> {
+    let server_config = ServerConfig::load(&data.args.server_config);
+    authenticate_admin(&req, &server_config)?;
+
+    let name = req.match_info().get("profile").unwrap_or_default();
+    let params = serde_json
+        ::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .filter(|value| value.is_object())
+        .and_then(|value| serde_yaml::to_value(value).ok())
+        .map(|value| profile::parse_profile_params(&value))
+        .ok_or(AppError::BadRequest("请求内容需要是JSON对象，例如 {\"target\": \"clash\"}".to_string()))?;
+    // 先按订阅链接的参数检查一遍，参数或者数据源无效就不保存
+    let uri_params = params_to_uri_params(params.clone(), None, &data, &server_config)?;
+    server_config.resolve_data_source(&uri_params.data_source)?;
+
+    let created = profile::save_profile(name, &params, &server_config)?;
+    let body = serde_json::json!({ "name": name, "url": format!("/s/{}", name) }).to_string();
+    let mut response = match created {
+        true => HttpResponse::Created(),
+        false => HttpResponse::Ok(),
+    };
+    Ok(response.content_type("application/json; charset=utf-8").body(body))
+}
+
+// 管理接口：删除通过管理接口保存的订阅配置
+#[delete("/admin/profiles/{profile}")]
+async fn admin_delete_profile(
+    req: HttpRequest,
+    data: web::Data<AppState>
+) -> Result<HttpResponse, AppError> {
+    let server_config = ServerConfig::load(&data.args.server_config);
+    authenticate_admin(&req, &server_config)?;
+
+    let name = req.match_info().get("profile").unwrap_or_default();
+    profile::delete_profile(name, &server_config)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// 列出模板目录中可用的模板（template=<name>使用）
+#[routes]
+#[get("/templates")]
+#[get("/templates/{token}")]
+async fn templates_list(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let server_config = ServerConfig::load(&data.args.server_config);
+    authenticate(&req, &server_config)?;
+
+    let templates: Vec<serde_json::Value> = template::list_templates(&TEMPLATES)
+        .into_iter()
+        .map(|t| serde_json::json!({ "target": t.target, "name": t.name, "file": t.file }))
+        .collect();
+    let body = serde_json::to_string_pretty(&templates).unwrap_or_default();
+
+    Ok(HttpResponse::Ok().content_type("application/json; charset=utf-8").body(body))
+}
+
+// 检查token，解析订阅链接的参数并应用token的限制，然后创建订阅，target不为None时固定转换为这个目标，
+// profile_name不为None时，先使用这个订阅配置中的参数
+fn subscribe(
+    req: &HttpRequest,
+    data: &AppState,
+    target: Option<&str>,
+    profile_name: Option<&str>
+) -> Result<HttpResponse, AppError> {
+    let mut server_config = ServerConfig::load(&data.args.server_config);
+    let access_token = authenticate(req, &server_config)?;
+    let profile_params = match profile_name {
+        Some(name) =>
+            profile
+                ::find_profile(name, &server_config)
+                .ok_or(AppError::NotFound(format!("没有名称为{}的订阅配置", name)))?.params,
+        None => Vec::new(),
+    };
+    let mut uri_params = parse_uri_params(req, data, &server_config, profile_params)?;
+    if let Some(target) = target {
+        uri_params.target = target.to_string();
+    }
+    req.extensions_mut().insert(RequestTarget(uri_params.target.clone()));
+    // 数据源只能是服务器配置中允许的目录、主机或者别名
+    uri_params.data_source = server_config.resolve_data_source(&uri_params.data_source)?;
+    if let Some(access_token) = access_token {
+        let proxies_value: YamlValue = config::parse_file_to_yamlvlaue(&CONFIG_FILE);
+        if let Err(error) = auth::restrict_params(&mut uri_params, &access_token, &proxies_value) {
+            tracing::warn!("拒绝token（{}）的订阅请求：{}", access_token.name, error);
+            return Err(error);
+        }
+        // 每个token可以有自己的流量信息
+        if !access_token.userinfo.is_empty() {
+            server_config.userinfo = access_token.userinfo;
+        }
+    }
+
+    build_subscribe_response(uri_params, &server_config)
+}
+
+// 服务器配置中设置了auth.tokens时，检查请求中的token（路径/sub/<token>、token参数或者Authorization: Bearer <token>），
+// 没有token或者token无效返回401，没有设置auth.tokens时不需要token
+fn authenticate(req: &HttpRequest, server_config: &ServerConfig) -> Result<Option<AccessToken>, AppError> {
+    if server_config.tokens.is_empty() {
+        return Ok(None);
+    }
+    let token = req
+        .match_info()
+        .get("token")
+        .map(|token| token.to_string())
+        .or_else(|| {
+            from_str::<Vec<(String, String)>>(req.query_string())
+                .ok()?
+                .into_iter()
+                .find(|(key, _)| key.to_lowercase() == "token")
+                .map(|(_, value)| value)
+        })
+        .or_else(|| {
+            req.headers()
+                .get("Authorization")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .map(|token| token.trim().to_string())
+        });
+    match token {
+        Some(token) =>
+            match auth::find_access_token(&server_config.tokens, &token) {
+                Some(access_token) => Ok(Some(access_token.clone())),
+                None => Err(AppError::Unauthorized("token无效".to_string())),
+            }
+        None =>
+            Err(AppError::Unauthorized("需要token才能访问，请在订阅链接中添加token参数".to_string())),
+    }
+}
+
+// 检查管理接口的令牌（只支持Authorization: Bearer <token>），没有设置auth.admin_token时，管理接口不可用
+fn authenticate_admin(req: &HttpRequest, server_config: &ServerConfig) -> Result<(), AppError> {
+    if server_config.admin_token.is_empty() {
+        return Err(AppError::Forbidden("没有设置auth.admin_token，管理接口不可用".to_string()));
+    }
+    let token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|token| token.trim())
+        .unwrap_or_default();
+    match auth::verify_admin_token(&server_config.admin_token, token) {
+        true => Ok(()),
+        false => Err(AppError::Unauthorized("管理接口的token无效".to_string())),
+    }
+}
+
+// 读取config.yaml的节点配置，分拣数据以及创建订阅内容
+fn build_subscribe_response(
+    uri_params: Params,
+    server_config: &ServerConfig
+) -> Result<HttpResponse, AppError> {
+    let proxies_value: YamlValue = config::parse_file_to_yamlvlaue(&CONFIG_FILE);
+
+    // 分拣数据以及创建订阅内容
+    let started = Instant::now();
+    let subscription = build::sorting_data_and_build_subscribe(
+        proxies_value,
+        uri_params.clone(),
+        &TEMPLATES,
+        server_config
+    )?;
+    metrics::record_build(&uri_params.target, started.elapsed(), subscription.node_count);
+
+    // 不同转换目标的内容类型和文件扩展名
+    let (content_type, extension) = match uri_params.target.as_str() {
+        "clash" | "clash-provider" => ("text/yaml; charset=utf-8", "yaml"),
+        "singbox" | "xray" => ("application/json; charset=utf-8", "json"),
+        "surge" | "surfboard" | "quanx" | "loon" => ("text/plain; charset=utf-8", "conf"),
+        _ => ("text/plain; charset=utf-8", "txt"),
+    };
+    let file_name = match subscription.page > 1 {
+        true => format!("{}-{}-{}.{}", server_config.profile_name, uri_params.target, subscription.page, extension),
+        false => format!("{}-{}.{}", server_config.profile_name, uri_params.target, extension),
+    };
+    // filename只能是ASCII字符，非ASCII的文件名通过filename*传递
+    let ascii_name: String = file_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect();
+
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type(content_type)
+        .insert_header((
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+                ascii_name,
+                urlencoding::encode(&file_name)
+            ),
+        ))
+        .insert_header(("X-Page", subscription.page.to_string()))
+        .insert_header(("X-Total-Pages", subscription.total_pages.to_string()))
+        .insert_header(("X-Total-Addresses", subscription.total_addresses.to_string()))
+        .insert_header(("X-Total-Nodes", subscription.node_count.to_string()));
+    if server_config.update_interval > 0 {
+        response.insert_header(("profile-update-interval", server_config.update_interval.to_string()));
+    }
+    if !server_config.web_page_url.is_empty() {
+        response.insert_header(("profile-web-page-url", server_config.web_page_url.clone()));
+    }
+    if !server_config.userinfo.is_empty() {
+        response.insert_header(("subscription-userinfo", server_config.userinfo.clone()));
+    }
+
+    Ok(response.body(subscription.body))
+}
+
+// 解析订阅链接的参数，订阅配置（profile_params）中的参数在前，链接中的参数在后（覆盖配置中的同名参数）
+fn parse_uri_params(
+    req: &HttpRequest,
+    data: &AppState,
+    server_config: &ServerConfig,
+    profile_params: Vec<(String, String)>
+) -> Result<Params, AppError> {
     let query_str = req.query_string();
-    let params: Vec<(String, String)> = from_str(&query_str).expect("Failed to parse query string");
+    let query_params: Vec<(String, String)> = from_str(query_str).map_err(|_|
+        AppError::BadRequest("订阅链接的参数无法解析".to_string())
+    )?;
+    let params: Vec<(String, String)> = profile_params.into_iter().chain(query_params).collect();
+    let user_agent = req.headers().get("User-Agent").and_then(|v| v.to_str().ok());
+
+    params_to_uri_params(params, user_agent, data, server_config)
+}
+
+// 将参数转换为Params，没有传入的参数使用默认值（部分默认值由cli参数传递进来），
+// 覆盖模板设置的参数无效时，返回错误原因
+fn params_to_uri_params(
+    params: Vec<(String, String)>,
+    user_agent: Option<&str>,
+    data: &AppState,
+    server_config: &ServerConfig
+) -> Result<Params, AppError> {
 
     let mut uri_params = Params {
         target: data.args.target.to_string(), // 由cli参数中传递进来，默认转换为v2ray，可以在订阅链接中修改
@@ -87,39 +549,94 @@ async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
         userid: 0, // 选择yaml中哪个节点配置（index）
         column_name: "colo".to_string(), // 使用哪个列名的字段值为节点的前缀？可选：[colo,loc,region,city]
         template: true, // 是否使用模板文件，默认使用
+        template_name: "".to_string(), // 使用模板目录中哪个模板，空为默认模板
+        minimal: false, // template=false时，是否生成最小的可运行配置（只对clash、singbox有效）
         proxy_type: "all".to_string(), // 不区分代理的类型（vles、trojan）
         tls_mode: "all".to_string(), // 选择哪些端口？true/1是选择TLS端口，false/0选择非TLS的端口，其它就不区分
         data_source: "./data".to_string(), // 默认数据文件路径
         page: 1,
+        http_ports: data.args.http_ports.clone(), // 非TLS的端口池，由cli参数传递进来，可以在订阅链接中修改
+        https_ports: data.args.https_ports.clone(), // TLS的端口池
+        port_mode: "auto".to_string(), // 端口分配策略：[auto,keep,random,fixed,roundrobin]
+        fixed_port: 0, // portmode=fixed时使用的端口
+        expand_ports: false, // 是否将每个地址展开到端口池的所有端口
+        seed: None, // 随机种子，没有传入seed参数时，由请求参数生成稳定的种子
+        pairing: "random".to_string(), // 地址跟节点配置的配对方式：[random,roundrobin,cartesian]
+        node_cap: 0, // 每个节点配置最多生成多少个节点，0为不限制
+        encode: data.args.encode.to_string(), // v2ray订阅的编码方式：[base64,base64url,raw]
+        singbox_version: None, // sing-box配置的版本（1.x中的x），None为没有传入也识别不到，使用模板原有的旧版写法
+        placement: "append".to_string(), // sing-box节点放在outbounds中的位置：[append,after:<tag>]
+        group_by: "".to_string(), // 按哪个字段自动生成代理组：[colo,loc,region,city,node]，空为不生成
+        overrides: Vec::new(), // 覆盖模板中的设置：[mixed_port,tun,allow_lan,dns,loglevel]
+        allowed_ids: Vec::new(), // token限制使用的节点配置（序号），为空不限制
+        allowed_names: Vec::new(), // token限制使用的节点配置（名称），为空不限制
+        node_limit: 0, // token限制每次订阅最多返回的节点数，0为不限制
     };
 
+    // 从User-Agent中识别sing-box客户端的版本，sbver参数优先
+    if let Some(user_agent) = user_agent {
+        uri_params.singbox_version = singbox::detect_singbox_version(user_agent);
+    }
+
+    // 没有传入seed参数时，使用请求参数生成稳定的种子，相同的订阅链接总是生成相同的内容
+    // （seed、encode、raw、sbver、placement、groupby、template、minimal、token以及覆盖模板设置的参数不影响节点的分配，不参与生成种子）
+    let output_only_keys = [
+        "seed", "encode", "raw", "sbver", "singboxversion",
+        "placement", "insert", "groupby", "template", "minimal", "token",
+    ];
+    let mut sorted_params: Vec<String> = params
+        .iter()
+        .filter(|(key, _)| {
+            let key = key.to_lowercase();
+            !output_only_keys.contains(&key.as_str()) &&
+                !overrides::OVERRIDE_KEYS.contains(&key.as_str()) &&
+                !key.starts_with("override.")
+        })
+        .map(|(key, value)| format!("{}={}", key.to_lowercase(), value))
+        .collect();
+    sorted_params.sort();
+    uri_params.seed = Some(convert::stable_seed(&sorted_params.join("&")));
+
     // 获取url的参数
     for (key, value) in params {
         if key.to_lowercase() == "target" {
-            uri_params.target = value.to_string().to_string();
-        } else if vec!["n", "nodesize", "nodecount"].contains(&key.to_lowercase().as_str()) {
-            uri_params.node_count = value.parse::<usize>().unwrap_or(uri_params.node_count);
-        } else if vec!["dport", "defaultport"].contains(&key.to_lowercase().as_str()) {
+            uri_params.target = value.to_lowercase();
+        } else if ["n", "nodesize", "nodecount"].contains(&key.to_lowercase().as_str()) {
+            uri_params.node_count = value
+                .parse::<usize>()
+                .ok()
+                .filter(|&count| count > 0)
+                .unwrap_or(uri_params.node_count);
+        } else if ["dport", "defaultport"].contains(&key.to_lowercase().as_str()) {
             if let Ok(port) = value.parse::<u16>() {
                 if (80..65535).contains(&port) {
                     uri_params.default_port = port;
                 }
             }
-        } else if vec!["id", "userid"].contains(&key.to_lowercase().as_str()) {
-            if let Ok(1..=255) = value.parse::<u8>() {
-                uri_params.userid = value.parse::<u8>().unwrap();
+        } else if ["id", "userid"].contains(&key.to_lowercase().as_str()) {
+            if let Ok(userid @ 1..=255) = value.parse::<u8>() {
+                uri_params.userid = userid;
             }
         } else if key.to_lowercase() == "page" {
             uri_params.page = value.parse().unwrap_or(uri_params.page).max(1);
         } else if key.to_lowercase() == "template" {
-            uri_params.template = value.parse::<bool>().unwrap_or(true);
-        } else if vec!["type", "proxy", "proxytype"].contains(&key.to_lowercase().as_str()) {
+            // true/false兼容旧版本，其它值为模板目录中的模板名称
+            match value.to_lowercase().as_str() {
+                "true" | "false" => uri_params.template = value.to_lowercase() == "true",
+                _ => {
+                    uri_params.template = true;
+                    uri_params.template_name = value.to_string();
+                }
+            }
+        } else if key.to_lowercase() == "minimal" {
+            uri_params.minimal = matches!(value.to_lowercase().as_str(), "1" | "true");
+        } else if ["type", "proxy", "proxytype"].contains(&key.to_lowercase().as_str()) {
             uri_params.proxy_type = value.to_string();
-        } else if vec!["column", "columnname"].contains(&key.to_lowercase().as_str()) {
+        } else if ["column", "columnname"].contains(&key.to_lowercase().as_str()) {
             uri_params.column_name = value.to_string(); // 以哪个列的字段名作为前缀？[colo,loc,region,city]
-        } else if vec!["source", "datasource"].contains(&key.to_lowercase().as_str()) {
-            uri_params.data_source = value.to_string(); // 数据文件路径，支持相对路径和绝对路径
-        } else if vec!["tls", "mode", "tls_mode"].contains(&key.to_lowercase().as_str()) {
+        } else if ["source", "datasource"].contains(&key.to_lowercase().as_str()) {
+            uri_params.data_source = value.to_string(); // 数据文件路径、https链接或者别名（服务器配置中的data_sources）
+        } else if ["tls", "mode", "tls_mode"].contains(&key.to_lowercase().as_str()) {
             match value.to_string().to_lowercase().as_str() {
                 "1" | "true" => {
                     uri_params.tls_mode = "true".to_string();
@@ -129,20 +646,95 @@ async fn subconverter(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
                 }
                 _ => {}
             }
+        } else if ["portmode", "port_mode"].contains(&key.to_lowercase().as_str()) {
+            let port_mode = value.to_lowercase();
+            if ["auto", "keep", "random", "fixed", "roundrobin"].contains(&port_mode.as_str()) {
+                uri_params.port_mode = port_mode;
+            }
+        } else if ["fport", "fixedport"].contains(&key.to_lowercase().as_str()) {
+            uri_params.fixed_port = value.parse::<u16>().unwrap_or(uri_params.fixed_port);
+        } else if ["expand", "expandports", "allports"].contains(&key.to_lowercase().as_str()) {
+            uri_params.expand_ports = matches!(value.to_lowercase().as_str(), "1" | "true");
+        } else if ["httpports", "http_ports"].contains(&key.to_lowercase().as_str()) {
+            uri_params.http_ports = parse_ports(&value).unwrap_or(uri_params.http_ports);
+        } else if ["httpsports", "https_ports"].contains(&key.to_lowercase().as_str()) {
+            uri_params.https_ports = parse_ports(&value).unwrap_or(uri_params.https_ports);
+        } else if key.to_lowercase() == "pairing" {
+            let pairing = value.to_lowercase();
+            if ["random", "roundrobin", "cartesian"].contains(&pairing.as_str()) {
+                uri_params.pairing = pairing;
+            }
+        } else if ["cap", "nodecap", "maxpernode"].contains(&key.to_lowercase().as_str()) {
+            uri_params.node_cap = value.parse::<usize>().unwrap_or(uri_params.node_cap);
+        } else if key.to_lowercase() == "encode" {
+            match value.to_lowercase().as_str() {
+                "base64" | "b64" => uri_params.encode = "base64".to_string(),
+                "base64url" | "urlsafe" => uri_params.encode = "base64url".to_string(),
+                "raw" | "plain" | "none" => uri_params.encode = "raw".to_string(),
+                _ => {}
+            }
+        } else if key.to_lowercase() == "raw" {
+            // raw=true跟旧版本一样，返回没有编码的分享链接
+            match value.to_lowercase().as_str() {
+                "1" | "true" => uri_params.encode = "raw".to_string(),
+                "0" | "false" if uri_params.encode == "raw" => uri_params.encode = "base64".to_string(),
+                _ => {}
+            }
+        } else if ["sbver", "singboxversion"].contains(&key.to_lowercase().as_str()) {
+            uri_params.singbox_version = singbox::parse_singbox_version(&value).or(uri_params.singbox_version);
+        } else if ["placement", "insert"].contains(&key.to_lowercase().as_str()) {
+            // append：追加到outbounds的最后；after:<tag>：放在指定tag的出站后面
+            if value == "append" || value.starts_with("after:") {
+                uri_params.placement = value.to_string();
+            }
+        } else if key.to_lowercase() == "groupby" {
+            let group_by = value.to_lowercase();
+            if ["colo", "loc", "region", "city", "node"].contains(&group_by.as_str()) {
+                uri_params.group_by = group_by;
+            }
+        } else if key.to_lowercase() == "seed" {
+            // seed=random表示每次都随机，数字直接作为种子，其它字符串转换为种子
+            uri_params.seed = match value.to_lowercase().as_str() {
+                "random" | "rand" => None,
+                seed => Some(seed.parse::<u64>().unwrap_or(convert::stable_seed(seed))),
+            };
+        } else if overrides::OVERRIDE_KEYS.contains(&key.to_lowercase().as_str()) {
+            uri_params.overrides.push(overrides::parse_override(&key, &value)?);
+        } else if let Some(override_key) = key.to_lowercase().strip_prefix("override.") {
+            // override.<名称>的写法，名称不在支持的范围内就报错
+            uri_params.overrides.push(overrides::parse_override(override_key, &value)?);
         }
     }
 
-    let proxies_value: YamlValue = config::parse_file_to_yamlvlaue(&CONFIG_FILE);
+    // 覆盖参数修改的是模板中的设置，template=false时需要minimal=true（使用最小配置作为模板）
+    if !uri_params.overrides.is_empty() && !uri_params.template && !uri_params.minimal {
+        return Err(AppError::BadRequest("template=false时不能使用覆盖模板设置的参数，可以同时设置minimal=true".to_string()));
+    }
 
-    // 分拣数据以及创建订阅内容
-    let html_body = build::sorting_data_and_build_subscribe(
-        proxies_value,
-        uri_params.clone(),
-        &CLASH_TEMPLATE,
-        &SINGBOX_TEMPLATE
-    );
+    // target=auto时，根据User-Agent选择转换目标，识别不到就使用v2ray
+    if uri_params.target.to_lowercase() == "auto" {
+        uri_params.target = user_agent
+            .and_then(|user_agent| server_config.detect_target(user_agent))
+            .unwrap_or("v2ray".to_string());
+    }
+    // 不支持的转换目标直接返回400，不再读取数据和节点配置
+    if !server_config::TARGETS.contains(&uri_params.target.as_str()) {
+        return Err(AppError::BadRequest(format!("不支持的转换目标：{}，可选auto、{}", uri_params.target, server_config::TARGETS.join("、"))));
+    }
+
+    Ok(uri_params)
+}
 
-    HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(html_body)
+// 解析逗号分隔的端口列表，有无效的端口或者为空，就返回None
+fn parse_ports(value: &str) -> Option<Vec<u16>> {
+    let ports: Vec<u16> = value
+        .split(',')
+        .map(|port| port.trim().parse::<u16>().ok().filter(|&port| port > 0))
+        .collect::<Option<Vec<u16>>>()?;
+    match ports.is_empty() {
+        true => None,
+        false => Some(ports),
+    }
 }
 
 #[actix_web::main]
@@ -151,6 +743,7 @@ async fn main() -> std::io::Result<()> {
     let result = Args::try_parse();
     match result {
         Ok(args) => {
+            access_log::init_logging();
             // 将args的cli参数值分享/传递给subconverter函数中使用
             let shared_state = web::Data::new(AppState { args: args.clone() });
             // 获取本机的私有IP地址
@@ -161,23 +754,153 @@ async fn main() -> std::io::Result<()> {
                     return Ok(());
                 }
             };
-            // 绑定的端口
+            // 绑定的端口，没有设置--bind时，监听0.0.0.0:<port>
             let port = args.port;
-            println!(
-                "Server is running on http://{}:{} or http://127.0.0.1:{}",
-                local_ip.to_string(),
-                port,
-                port
-            );
-            return HttpServer::new(move || {
+            let binds = match args.bind.is_empty() {
+                true => vec![format!("0.0.0.0:{}", port)],
+                false => args.bind.clone(),
+            };
+
+            // 同时设置了证书和私钥时，TCP地址使用HTTPS（Unix socket仍然是HTTP，由反向代理负责HTTPS）
+            let tls_config = match (&args.tls_cert, &args.tls_key) {
+                (Some(cert_file), Some(key_file)) => {
+                    let resolver = match CertResolver::new(cert_file, key_file) {
+                        Ok(resolver) => Arc::new(resolver),
+                        Err(e) => {
+                            eprintln!("加载HTTPS证书失败：{}", e);
+                            return Ok(());
+                        }
+                    };
+                    #[cfg(unix)]
+                    reload_cert_on_sighup(resolver.clone());
+                    match https::build_server_config(resolver) {
+                        Ok(config) => Some(config),
+                        Err(e) => {
+                            eprintln!("创建HTTPS配置失败：{}", e);
+                            return Ok(());
+                        }
+                    }
+                }
+                (None, None) => None,
+                _ => {
+                    eprintln!("--tls-cert和--tls-key需要一起设置");
+                    return Ok(());
+                }
+            };
+            if tls_config.is_none() && !args.redirect_bind.is_empty() {
+                eprintln!("--redirect-bind需要启用HTTPS（--tls-cert、--tls-key）");
+                return Ok(());
+            }
+            let scheme = match tls_config.is_some() {
+                true => "https",
+                false => "http",
+            };
+
+            if ServerConfig::load(&args.server_config).tokens.is_empty() {
+                println!("注意：服务器配置中没有设置auth.tokens，任何人都可以访问订阅");
+            }
+            let mut server = HttpServer::new(move || {
                 App::new()
                     .app_data(shared_state.clone())
+                    // 访问日志和请求数的指标，每个请求都有一个请求ID（响应头X-Request-Id）
+                    .wrap_fn(|req, srv| {
+                        let started = Instant::now();
+                        let request_id = access_log::request_id(
+                            req
+                                .headers()
+                                .get("X-Request-Id")
+                                .and_then(|v| v.to_str().ok())
+                        );
+                        let span = tracing::info_span!("request", request_id = %request_id);
+                        let future = srv.call(req);
+                        async move {
+                            let mut response = future.await?;
+                            log_request(&mut response, &request_id, started);
+                            Ok(response)
+                        }.instrument(span)
+                    })
                     .service(index)
                     .service(subconverter)
+                    .service(provider)
+                    .service(templates_list)
+                    .service(saved_profile)
+                    .service(admin_list_profiles)
+                    .service(admin_save_profile)
+                    .service(admin_delete_profile)
+                    .service(metrics_export)
                     .default_service(actix_web::web::route().to(default_route))
-            })
-                .bind(format!("0.0.0.0:{}", port))?
-                .run().await;
+            });
+            for bind in &binds {
+                match bind.strip_prefix("unix:") {
+                    Some(socket_path) => {
+                        #[cfg(unix)]
+                        {
+                            // 删除上次运行留下的socket文件（只删除socket，不删除普通文件）
+                            use std::os::unix::fs::FileTypeExt;
+                            if
+                                std::fs
+                                    ::symlink_metadata(socket_path)
+                                    .is_ok_and(|meta| meta.file_type().is_socket())
+                            {
+                                let _ = std::fs::remove_file(socket_path);
+                            }
+                            server = server.bind_uds(socket_path)?;
+                            println!("Server is running on unix:{}", socket_path);
+                        }
+                        #[cfg(not(unix))]
+                        {
+                            eprintln!("当前系统不支持Unix socket：{}", socket_path);
+                            return Ok(());
+                        }
+                    }
+                    None => {
+                        server = match &tls_config {
+                            Some(config) => server.bind_rustls_0_23(bind, config.clone())?,
+                            None => server.bind(bind)?,
+                        };
+                        match args.bind.is_empty() {
+                            true =>
+                                println!(
+                                    "Server is running on {}://{}:{} or {}://127.0.0.1:{}",
+                                    scheme,
+                                    local_ip,
+                                    port,
+                                    scheme,
+                                    port
+                                ),
+                            false => println!("Server is running on {}://{}", scheme, bind),
+                        }
+                    }
+                }
+            }
+
+            // HTTP重定向到第一个HTTPS地址的端口
+            let redirect_server = match args.redirect_bind.is_empty() {
+                true => None,
+                false => {
+                    let https_port = binds
+                        .iter()
+                        .filter(|bind| !bind.starts_with("unix:"))
+                        .find_map(|bind| bind.rsplit(':').next()?.parse::<u16>().ok())
+                        .unwrap_or(443);
+                    let mut redirect_server = HttpServer::new(move || {
+                        App::new()
+                            .app_data(web::Data::new(https_port))
+                            .default_service(web::route().to(redirect_to_https))
+                    });
+                    for bind in &args.redirect_bind {
+                        redirect_server = redirect_server.bind(bind)?;
+                        println!("Redirecting http://{} to HTTPS port {}", bind, https_port);
+                    }
+                    Some(redirect_server.run())
+                }
+            };
+
+            let server = server.run();
+            return match redirect_server {
+                Some(redirect_server) => tokio::try_join!(server, redirect_server).map(|_| ()),
+                None => server.await,
+            };
         }
         Err(e) => {
             if
@@ -194,3 +917,254 @@ async fn main() -> std::io::Result<()> {
     }
     return Ok(());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ body::MessageBody, http::StatusCode, test };
+    use std::path::{ Path, PathBuf };
+
+    // 每个测试使用自己的临时目录，目录中的server.yaml由测试写入（为空时使用默认的服务器配置）
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cfwks-main-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn app_state(dir: &Path, server_yaml: &str) -> web::Data<AppState> {
+        let server_config = dir.join("server.yaml");
+        std::fs::write(&server_config, server_yaml).unwrap();
+        let args = Args::parse_from(["cfwks", "--server-config", &server_config.to_string_lossy()]);
+        web::Data::new(AppState { args })
+    }
+
+    // 发送请求，返回状态码、响应头和响应内容
+    async fn call(
+        state: &web::Data<AppState>,
+        req: test::TestRequest
+    ) -> (StatusCode, actix_web::http::header::HeaderMap, String) {
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(subconverter)
+                .service(provider)
+                .service(templates_list)
+                .service(saved_profile)
+                .service(admin_list_profiles)
+                .service(admin_save_profile)
+                .service(admin_delete_profile)
+                .service(metrics_export)
+        ).await;
+        let response = test::call_service(&app, req.to_request()).await;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.into_body().try_into_bytes().unwrap_or_default();
+        (status, headers, String::from_utf8_lossy(&body).to_string())
+    }
+
+    #[actix_web::test]
+    async fn templates_are_listed_and_names_checked() {
+        let dir = test_dir("templates");
+        let state = app_state(&dir, "");
+        let (status, _, body) = call(&state, test::TestRequest::get().uri("/templates")).await;
+        assert_eq!(status, StatusCode::OK);
+        let templates: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        let names = |target: &str| -> Vec<String> {
+            templates
+                .iter()
+                .filter(|t| t["target"] == target)
+                .map(|t| t["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(names("clash"), ["default", "tun"]);
+        assert_eq!(names("singbox"), ["default", "desktop"]);
+        assert_eq!(names("surge"), ["default"]);
+
+        for (name, expected) in [
+            ("tun", StatusCode::OK),
+            ("desktop", StatusCode::NOT_FOUND),
+            ("..%2Fclash", StatusCode::NOT_FOUND),
+        ] {
+            let uri = format!("/sub?target=clash&n=2&template={}", name);
+            let (status, _, body) = call(&state, test::TestRequest::get().uri(&uri)).await;
+            assert_eq!(status, expected, "{} {}", name, body);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // 数据文件放在测试目录中，server.yaml允许读取这个目录
+    fn data_state(dir: &Path, data: &str, extra: &str) -> web::Data<AppState> {
+        std::fs::write(dir.join("ip.txt"), data).unwrap();
+        let server_yaml = format!(
+            "data_sources:\n  allowed_roots:\n    - {}\n{}",
+            dir.to_string_lossy(),
+            extra
+        );
+        app_state(dir, &server_yaml)
+    }
+
+    fn header<'a>(headers: &'a actix_web::http::header::HeaderMap, name: &str) -> &'a str {
+        headers.get(name).and_then(|value| value.to_str().ok()).unwrap_or_default()
+    }
+
+    #[actix_web::test]
+    async fn subscription_headers_describe_the_page() {
+        let dir = test_dir("headers");
+        let state = data_state(
+            &dir,
+            "1.1.1.1:443\n2.2.2.2:443\n3.3.3.3:443\n4.4.4.4:443\n5.5.5.5:443\n",
+            "subscription:\n  profile_name: test\n  update_interval: 12\n"
+        );
+        let source = urlencoding::encode(&dir.join("ip.txt").to_string_lossy()).to_string();
+
+        // 5个地址每页2个，最后一页只有1个地址
+        let uri = format!("/sub?target=v2ray&id=1&n=2&page=3&encode=raw&source={}", source);
+        let (status, headers, body) = call(&state, test::TestRequest::get().uri(&uri)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(header(&headers, "X-Page"), "3");
+        assert_eq!(header(&headers, "X-Total-Pages"), "3");
+        assert_eq!(header(&headers, "X-Total-Addresses"), "5");
+        assert_eq!(header(&headers, "X-Total-Nodes"), body.lines().count().to_string());
+        assert_eq!(header(&headers, "profile-update-interval"), "12");
+        assert_eq!(
+            header(&headers, "Content-Disposition"),
+            "attachment; filename=\"test-v2ray-3.txt\"; filename*=UTF-8''test-v2ray-3.txt"
+        );
+
+        // 第一页的文件名不带页码
+        let uri = format!("/sub?target=clash&id=1&n=2&source={}", source);
+        let (status, headers, _) = call(&state, test::TestRequest::get().uri(&uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(header(&headers, "X-Page"), "1");
+        assert!(header(&headers, "Content-Disposition").contains("filename=\"test-clash.yaml\""));
+
+        // 超出范围的页码
+        let uri = format!("/sub?target=v2ray&id=1&n=2&page=4&source={}", source);
+        let (status, headers, body) = call(&state, test::TestRequest::get().uri(&uri)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(headers.get("X-Page").is_none());
+        assert!(body.contains("第4页超出范围，共3页"), "{}", body);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn errors_have_matching_status_codes() {
+        let dir = test_dir("errors");
+        // 关闭监听后这个端口不再有服务，获取远程模板会失败
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let state = app_state(
+            &dir,
+            &format!("remote_templates:\n  allowed_hosts:\n    - http://127.0.0.1:{}\n", closed_port)
+        );
+        let remote = urlencoding::encode(&format!("http://127.0.0.1:{}/clash.yaml", closed_port)).to_string();
+        for (uri, expected) in [
+            ("/sub?target=foo".to_string(), StatusCode::BAD_REQUEST),
+            ("/sub?target=clash&source=/etc/passwd".to_string(), StatusCode::FORBIDDEN),
+            ("/sub?target=clash&template=missing".to_string(), StatusCode::NOT_FOUND),
+            ("/s/missing".to_string(), StatusCode::NOT_FOUND),
+            (format!("/sub?target=clash&n=2&template={}", remote), StatusCode::BAD_GATEWAY),
+        ] {
+            let (status, _, body) = call(&state, test::TestRequest::get().uri(&uri)).await;
+            assert_eq!(status, expected, "{} {}", uri, body);
+            assert!(!body.is_empty(), "{}", uri);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn http_is_redirected_to_https() {
+        for (https_port, host, uri, expected) in [
+            (8443u16, "example.com:80", "/sub?target=clash&id=1", "https://example.com:8443/sub?target=clash&id=1"),
+            (443, "example.com:8080", "/s/home", "https://example.com/s/home"),
+            (443, "example.com", "/", "https://example.com/"),
+            (8443, "[::1]:80", "/sub?n=2", "https://[::1]:8443/sub?n=2"),
+            (8443, "[::1]", "/", "https://[::1]:8443/"),
+        ] {
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(https_port))
+                    .default_service(web::route().to(redirect_to_https))
+            ).await;
+            let req = test::TestRequest::get().uri(uri).insert_header(("Host", host)).to_request();
+            let response = test::call_service(&app, req).await;
+            assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(header(response.headers(), "Location"), expected, "{} {}", host, uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn admin_requires_a_configured_token() {
+        let dir = test_dir("admin-auth");
+        let state = app_state(&dir, "");
+        let (status, _, _) = call(&state, test::TestRequest::get().uri("/admin/profiles")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let state = app_state(&dir, "auth:\n  admin_token: admin-secret\n");
+        for authorization in [None, Some("Bearer wrong"), Some("admin-secret")] {
+            let mut req = test::TestRequest::put().uri("/admin/profiles/home").set_payload("{}");
+            if let Some(authorization) = authorization {
+                req = req.insert_header(("Authorization", authorization));
+            }
+            let (status, _, _) = call(&state, req).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{:?}", authorization);
+        }
+        assert!(!dir.join("profiles.yaml").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn profiles_are_managed_through_admin_api() {
+        let dir = test_dir("admin-profiles");
+        let profiles_file = dir.join("profiles.yaml");
+        let state = app_state(
+            &dir,
+            &format!("auth:\n  admin_token: admin-secret\nprofiles_file: {}\n", profiles_file.to_string_lossy())
+        );
+        let admin = |req: test::TestRequest| req.insert_header(("Authorization", "Bearer admin-secret"));
+        let put = |name: &str, body: &str| {
+            admin(test::TestRequest::put().uri(&format!("/admin/profiles/{}", name)).set_payload(body.to_string()))
+        };
+
+        let (status, _, body) = call(&state, put("phone", r#"{"target": "v2ray", "id": 1, "n": 2}"#)).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        assert_eq!(body, r#"{"name":"phone","url":"/s/phone"}"#);
+        let (status, _, _) = call(&state, put("phone", r#"{"target": "v2ray", "id": 1, "n": 3}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _, body) = call(&state, admin(test::TestRequest::get().uri("/admin/profiles"))).await;
+        assert_eq!(status, StatusCode::OK);
+        let profiles: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            profiles,
+            serde_json::json!([
+                { "name": "phone", "url": "/s/phone", "editable": true, "params": { "target": "v2ray", "id": "1", "n": "3" } }
+            ])
+        );
+
+        let (status, _, body) = call(&state, test::TestRequest::get().uri("/s/phone")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        // 名称无效、参数无效时不保存
+        for (name, payload, expected) in [
+            ("bad.name", "{}", StatusCode::BAD_REQUEST),
+            ("..%2Fx", "{}", StatusCode::BAD_REQUEST),
+            ("other", "[1]", StatusCode::BAD_REQUEST),
+            ("other", r#"{"source": "/etc/passwd"}"#, StatusCode::FORBIDDEN),
+        ] {
+            let (status, _, body) = call(&state, put(name, payload)).await;
+            assert_eq!(status, expected, "{} {} {}", name, payload, body);
+        }
+        let (_, _, body) = call(&state, admin(test::TestRequest::get().uri("/admin/profiles"))).await;
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap().as_array().unwrap().len(), 1);
+
+        let delete = || admin(test::TestRequest::delete().uri("/admin/profiles/phone"));
+        let (status, _, _) = call(&state, delete()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _, _) = call(&state, delete()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _, _) = call(&state, test::TestRequest::get().uri("/s/phone")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
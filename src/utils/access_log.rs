@@ -0,0 +1,204 @@
+use super::server_config::TARGETS;
+use rand::Rng;
+use reqwest::Url;
+use std::io::IsTerminal;
+use tracing_subscriber::EnvFilter;
+
+// 这些参数的值不写入日志（名称不区分大小写，包含其中一个就算）
+const SECRET_KEYS: [&str; 6] = ["token", "secret", "password", "passwd", "uuid", "key"];
+
+/// 订阅请求的转换目标，由处理函数保存到请求中，访问日志和请求数的指标使用
+pub struct RequestTarget(pub String);
+
+/// 初始化访问日志，日志级别由环境变量RUST_LOG设置（默认为info，例如 RUST_LOG=warn 不输出访问日志），
+/// 输出到终端以外的地方（如日志文件）时不使用颜色
+pub fn init_logging() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .with_ansi(std::io::stdout().is_terminal())
+        .init();
+}
+
+/// 请求ID：使用请求头X-Request-Id（只能是字母、数字、-和_，最多64个字符），
+/// 没有或者无效就随机生成16个十六进制字符
+pub fn request_id(header: Option<&str>) -> String {
+    let valid = |id: &&str| {
+        !id.is_empty()
+            && id.len() <= 64
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    match header.map(|id| id.trim()).filter(valid) {
+        Some(id) => id.to_string(),
+        None => format!("{:016x}", rand::thread_rng().gen::<u64>()),
+    }
+}
+
+/// 隐藏查询字符串中token等参数的值，例如 target=clash&token=abc => target=clash&token=***，
+/// 值为链接时（如source=https://host/a.csv?token=abc），去掉链接中的用户名、密码和参数
+pub fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) if !value.is_empty() && is_secret_key(key) => {
+                format!("{}=***", key)
+            }
+            Some((key, value)) => match redact_nested_url(value) {
+                Some(url) => format!("{}={}", key, url),
+                None => pair.to_string(),
+            },
+            None => pair.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+/// 访问日志和指标中的转换目标：没有到转换目标这一步为none，不支持的转换目标为invalid（避免指标的标签值无限增加）
+pub fn target_label(target: &str) -> &str {
+    match target {
+        "" => "none",
+        target if TARGETS.contains(&target) => target,
+        _ => "invalid",
+    }
+}
+
+/// 隐藏路径中的token（/sub/<token>、/provider/<token>、/s/<名称>/<token>，token都是最后一段）
+pub fn redact_path(path: &str, has_token: bool) -> String {
+    match (has_token, path.rsplit_once('/')) {
+        (true, Some((prefix, _))) => format!("{}/***", prefix),
+        _ => path.to_string(),
+    }
+}
+
+/// 链接只保留协议、主机和路径，去掉用户名、密码和参数（可能含有token），不是链接就原样返回
+pub fn redact_url(source: &str) -> String {
+    match Url::parse(source) {
+        Ok(url) if url.has_host() => format!(
+            "{}://{}{}{}",
+            url.scheme(),
+            url.host_str().unwrap_or_default(),
+            url.port()
+                .map(|port| format!(":{}", port))
+                .unwrap_or_default(),
+            url.path()
+        ),
+        _ => source.replace('\\', "/"),
+    }
+}
+
+/// 日志中的数据源：链接按redact_url处理，本地路径只保留文件（或文件夹）名，不暴露服务器的目录结构
+pub fn redact_source(source: &str) -> String {
+    match Url::parse(source) {
+        Ok(url) if url.has_host() => redact_url(source),
+        _ => {
+            let source = source.replace('\\', "/");
+            let name = source
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or_default();
+            match name.is_empty() {
+                true => "***".to_string(),
+                false => format!(".../{}", name),
+            }
+        }
+    }
+}
+
+// 参数的值（可能经过URL编码）是含有用户名、密码、参数的链接时，返回隐藏后的链接，否则返回None
+fn redact_nested_url(value: &str) -> Option<String> {
+    let decoded = urlencoding::decode(value)
+        .map(|value| value.into_owned())
+        .unwrap_or_else(|_| value.to_string());
+    match Url::parse(&decoded) {
+        Ok(url)
+            if url.has_host()
+                && (url.query().is_some()
+                    || url.fragment().is_some()
+                    || !url.username().is_empty()
+                    || url.password().is_some()) =>
+        {
+            Some(redact_url(&decoded))
+        }
+        _ => None,
+    }
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = urlencoding::decode(key)
+        .map(|key| key.to_lowercase())
+        .unwrap_or_else(|_| key.to_lowercase());
+    SECRET_KEYS.iter().any(|secret| key.contains(secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_query_values_are_hidden() {
+        assert_eq!(
+            redact_query("target=clash&token=abc&Api%5FKey=1&password="),
+            "target=clash&token=***&Api%5FKey=***&password="
+        );
+    }
+
+    #[test]
+    fn nested_url_queries_are_hidden() {
+        assert_eq!(
+            redact_query("source=https://user:pw@example.com:8443/a.csv?token=abc&target=clash"),
+            "source=https://example.com:8443/a.csv&target=clash"
+        );
+        assert_eq!(
+            redact_query("template=https%3A%2F%2Fexample.com%2Ft.yaml%3Fsig%3Dabc"),
+            "template=https://example.com/t.yaml"
+        );
+        assert_eq!(
+            redact_query("source=https://example.com/a.csv&n=10"),
+            "source=https://example.com/a.csv&n=10"
+        );
+    }
+
+    #[test]
+    fn token_path_segment_is_hidden() {
+        assert_eq!(redact_path("/sub/abc", true), "/sub/***");
+        assert_eq!(redact_path("/s/home/abc", true), "/s/home/***");
+        assert_eq!(redact_path("/sub", false), "/sub");
+    }
+
+    #[test]
+    fn data_sources_are_hidden() {
+        assert_eq!(
+            redact_source("/root/crate/data/result.csv"),
+            ".../result.csv"
+        );
+        assert_eq!(redact_source("C:\\data\\ip.txt"), ".../ip.txt");
+        assert_eq!(redact_source("/srv/data/"), ".../data");
+        assert_eq!(redact_source("/"), "***");
+        assert_eq!(
+            redact_source("https://user:pw@example.com/a.csv?token=abc"),
+            "https://example.com/a.csv"
+        );
+    }
+
+    #[test]
+    fn target_labels_are_bounded() {
+        assert_eq!(target_label(""), "none");
+        assert_eq!(target_label("clash"), "clash");
+        assert_eq!(target_label("Clash"), "invalid");
+        assert_eq!(target_label("foo"), "invalid");
+    }
+
+    #[test]
+    fn request_ids_are_validated() {
+        assert_eq!(request_id(Some(" abc-123_x ")), "abc-123_x");
+        for header in [None, Some(""), Some("a b"), Some(&"x".repeat(65)[..])] {
+            let id = request_id(header);
+            assert_eq!(id.len(), 16);
+            assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use super::{config::get_yaml_value, error::AppError};
+use crate::Params;
+use serde_yaml::Value as YamlValue;
+
+/// 订阅的访问令牌（server.yaml的auth.tokens）以及这个令牌的限制，列表为空或者为0就不限制
+#[derive(Clone, Default)]
+pub struct AccessToken {
+    pub token: String,
+    pub name: String,         // 令牌的名称（用户），只用于提示
+    pub ids: Vec<usize>,      // 允许使用的节点配置（config.yaml中第几个节点）
+    pub tags: Vec<String>,    // 允许使用的节点配置（config.yaml中节点的name）
+    pub targets: Vec<String>, // 允许的转换目标
+    pub max_nodes: usize,     // 每次订阅最多返回的节点数
+    pub userinfo: String,     // 这个令牌的subscription-userinfo，为空就使用subscription.userinfo
+}
+
+/// 解析一个令牌的配置，没有token字段或者token为空就返回None
+pub fn parse_access_token(value: &YamlValue, userinfo: String) -> Option<AccessToken> {
+    let get_list = |key: &str| -> Vec<String> {
+        get_yaml_value(value, &[key])
+            .and_then(|v| v.as_sequence())
+            .map(|seq| {
+                seq.iter()
+                    .filter_map(|v| match v {
+                        YamlValue::String(s) => Some(s.trim().to_string()),
+                        YamlValue::Number(n) => Some(n.to_string()),
+                        _ => None,
+                    })
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let token = get_yaml_value(value, &["token"])
+        .and_then(|v| v.as_str())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    let token = match token {
+        Some(token) => token,
+        None => {
+            tracing::warn!("auth.tokens中有没有设置token的配置，已忽略");
+            return None;
+        }
+    };
+
+    Some(AccessToken {
+        name: get_yaml_value(value, &["name"])
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        ids: get_list("ids")
+            .iter()
+            .filter_map(|id| id.parse::<usize>().ok().filter(|&id| id > 0))
+            .collect(),
+        tags: get_list("tags"),
+        targets: get_list("targets")
+            .iter()
+            .map(|target| target.to_lowercase())
+            .collect(),
+        max_nodes: get_yaml_value(value, &["max_nodes"])
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0) as usize,
+        token,
+        userinfo,
+    })
+}
+
+/// 查找跟请求中的令牌相同的配置
+pub fn find_access_token<'a>(tokens: &'a [AccessToken], token: &str) -> Option<&'a AccessToken> {
+    tokens
+        .iter()
+        .find(|access_token| token_eq(&access_token.token, token))
+}
+
+/// 检查管理接口的令牌，没有设置admin_token时总是不通过
+pub fn verify_admin_token(admin_token: &str, token: &str) -> bool {
+    !admin_token.is_empty() && token_eq(admin_token, token)
+}
+
+// 逐字节比较全部内容，比较的耗时跟令牌在哪里不同无关
+fn token_eq(expected: &str, token: &str) -> bool {
+    let (a, b) = (expected.as_bytes(), token.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// 将令牌的限制应用到订阅参数中，转换目标或者节点配置（ids、tags，all_proxies_yaml为config.yaml的节点配置）
+/// 不允许使用时，都返回403错误
+pub fn restrict_params(
+    uri_params: &mut Params,
+    access_token: &AccessToken,
+    all_proxies_yaml: &YamlValue,
+) -> Result<(), AppError> {
+    if !access_token.targets.is_empty() && !access_token.targets.contains(&uri_params.target) {
+        return Err(AppError::Forbidden(format!(
+            "这个token不允许转换为{}，只能使用：{}",
+            uri_params.target,
+            access_token.targets.join(", ")
+        )));
+    }
+    // 跟转换时一样，节点配置需要同时符合ids和tags的限制
+    let configs = all_proxies_yaml
+        .as_sequence()
+        .map(|seq| seq.as_slice())
+        .unwrap_or_default();
+    let allowed = |id: usize| {
+        let name = configs
+            .get(id - 1)
+            .and_then(|config| get_yaml_value(config, &["name"]))
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        (access_token.ids.is_empty() || access_token.ids.contains(&id))
+            && (access_token.tags.is_empty() || access_token.tags.iter().any(|tag| tag == name))
+    };
+    if uri_params.userid > 0 && !allowed(uri_params.userid as usize) {
+        return Err(AppError::Forbidden(format!(
+            "这个token不允许使用第{}个节点配置",
+            uri_params.userid
+        )));
+    }
+    let restricted = !access_token.ids.is_empty() || !access_token.tags.is_empty();
+    if restricted && !(1..=configs.len()).any(allowed) {
+        return Err(AppError::Forbidden(
+            "这个token没有可以使用的节点配置".to_string(),
+        ));
+    }
+    uri_params.allowed_ids = access_token.ids.clone();
+    uri_params.allowed_names = access_token.tags.clone();
+    uri_params.node_limit = access_token.max_nodes;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access_token(yaml: &str) -> AccessToken {
+        parse_access_token(&serde_yaml::from_str(yaml).unwrap(), String::new()).unwrap()
+    }
+
+    #[test]
+    fn token_config_is_parsed() {
+        let token = access_token(
+            "{ token: abc, name: alice, ids: [1, 300, 0, x], tags: [hk], targets: [Clash], max_nodes: 3 }",
+        );
+        assert_eq!(token.ids, [1, 300]);
+        assert_eq!(token.tags, ["hk"]);
+        assert_eq!(token.targets, ["clash"]);
+        assert_eq!(token.max_nodes, 3);
+        assert!(parse_access_token(
+            &serde_yaml::from_str("{ token: ' ' }").unwrap(),
+            String::new()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn tokens_are_compared_exactly() {
+        let tokens = [access_token("{ token: abc }")];
+        assert!(find_access_token(&tokens, "abc").is_some());
+        assert!(find_access_token(&tokens, "ab").is_none());
+        assert!(find_access_token(&tokens, "abcd").is_none());
+        assert!(verify_admin_token("admin", "admin"));
+        assert!(!verify_admin_token("", ""));
+    }
+
+    fn configs() -> YamlValue {
+        serde_yaml::from_str("[{ name: hk }, { name: us }, { name: jp }]").unwrap()
+    }
+
+    #[test]
+    fn restrictions_are_applied_to_params() {
+        let token = access_token("{ token: abc, ids: [2, 300], targets: [clash], max_nodes: 3 }");
+        let mut params = Params {
+            target: "clash".to_string(),
+            ..Default::default()
+        };
+        restrict_params(&mut params, &token, &configs()).unwrap();
+        assert_eq!(params.allowed_ids, [2, 300]);
+        assert_eq!(params.node_limit, 3);
+
+        let mut params = Params {
+            target: "singbox".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            restrict_params(&mut params, &token, &configs()),
+            Err(AppError::Forbidden(_))
+        ));
+
+        let mut params = Params {
+            target: "clash".to_string(),
+            userid: 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            restrict_params(&mut params, &token, &configs()),
+            Err(AppError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn tag_restrictions_are_forbidden_too() {
+        let token = access_token("{ token: abc, tags: [us, jp] }");
+        for (userid, allowed) in [(0, true), (2, true), (3, true), (1, false)] {
+            let mut params = Params {
+                target: "clash".to_string(),
+                userid,
+                ..Default::default()
+            };
+            let result = restrict_params(&mut params, &token, &configs());
+            match allowed {
+                true => assert!(result.is_ok(), "{}", userid),
+                false => assert!(matches!(result, Err(AppError::Forbidden(_))), "{}", userid),
+            }
+        }
+
+        // ids和tags没有同时符合的节点配置
+        let token = access_token("{ token: abc, ids: [1], tags: [us] }");
+        let mut params = Params {
+            target: "clash".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            restrict_params(&mut params, &token, &configs()),
+            Err(AppError::Forbidden(_))
+        ));
+    }
+}
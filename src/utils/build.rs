@@ -1,25 +1,35 @@
 use super::{
     convert,
+    error::AppError,
     file_data::{self, MyData},
-    net_data,
+    group::{self, ProxyMeta},
+    net_data, overrides,
+    server_config::ServerConfig,
+    singbox, template,
 };
 use crate::Params;
 
-use lazy_static::lazy_static;
-use regex::Regex;
 use serde_json::{json, Value as JsonValue};
 use serde_yaml::Value as YamlValue;
+use std::collections::HashMap;
 
-lazy_static! {
-    // 匹配包含 "name:" 的 "- {}" 字符串，应用到clash相关代码中
-    static ref PROXYIES_NAME_REGEX: Regex = Regex::new(
-        r"  - \{([^}]*(name:[^}]*)[^}]*)\}"
-    ).unwrap();
-    static ref HTTP_PORTS: [u16; 7] = [80, 8080, 8880, 2052, 2082, 2086, 2095];
-    static ref HTTPS_PORTS: [u16; 6] = [443, 2053, 2083, 2087, 2096, 8443];
-}
+// 生成完整配置文件（或proxy-providers）的转换目标（限制每页的节点数，需要收集节点名称填充到代理组中）
+const PROFILE_TARGETS: [&str; 8] = [
+    "clash",
+    "clash-provider",
+    "singbox",
+    "xray",
+    "surge",
+    "surfboard",
+    "quanx",
+    "loon",
+];
 
-pub fn get_vec_data(uri_params: Params) -> Vec<Vec<MyData>> {
+/// 读取数据并按转换目标分页，没有读取到数据或者没有符合TLS模式的数据时，返回404错误
+pub fn get_vec_data(
+    uri_params: Params,
+    server_config: &ServerConfig,
+) -> Result<Vec<Vec<MyData>>, AppError> {
     // 针对win11中"复制文件地址"出现双引号的情况
     let trimmed_quotes_path = uri_params.data_source.trim_matches('"');
 
@@ -34,7 +44,8 @@ pub fn get_vec_data(uri_params: Params) -> Vec<Vec<MyData>> {
             uri_params.default_port,
             max_line,
             trimmed_quotes_path,
-        )
+            &server_config.data_hosts, // 重定向后的主机也必须是允许的
+        )?
     } else {
         // 传入的是本地文件路径，就从本地获取数据
         file_data::process_files_data(
@@ -42,17 +53,20 @@ pub fn get_vec_data(uri_params: Params) -> Vec<Vec<MyData>> {
             uri_params.default_port, // 没有找到端口的情况，就使用它
             max_line,                // 获取指定数量的数据就返回
             trimmed_quotes_path,     // 指定数据源所在文件夹路径或文件路径
-        )
+        )?
     };
+    if my_datas.is_empty() {
+        return Err(AppError::NotFound("数据源中没有读取到地址".to_string()));
+    }
 
-    if !my_datas.is_empty() {
+    {
         // ———————————————————————————————— 过滤不要的数据 ——————————————————————————————
 
         // 根据TLS模式是否开启，反向剔除不要端口的数据
         let filter_ports = match uri_params.tls_mode.as_str() {
-            "true" | "1" | "all" => HTTP_PORTS.to_vec(), // 过滤掉非TLS模式的端口
-            "false" | "0" => HTTPS_PORTS.to_vec(),       // 过滤掉TLS模式的端口
-            _ => HTTP_PORTS.to_vec(),
+            "true" | "1" | "all" => uri_params.http_ports.clone(), // 过滤掉非TLS模式的端口
+            "false" | "0" => uri_params.https_ports.clone(),       // 过滤掉TLS模式的端口
+            _ => uri_params.http_ports.clone(),
         };
         let filtered_data: Vec<MyData> = my_datas
             .iter()
@@ -66,16 +80,27 @@ pub fn get_vec_data(uri_params: Params) -> Vec<Vec<MyData>> {
             })
             .cloned()
             .collect();
+        if filtered_data.is_empty() {
+            return Err(AppError::NotFound(format!(
+                "数据源中没有符合tls={}的地址（端口）",
+                uri_params.tls_mode
+            )));
+        }
 
         // —————————————————————————————————— 数据分页 ——————————————————————————————————
 
         // 定义每页的最大长度（元素个数），主要限制singbox、clash配置文件最多节点数
-        let page_size = match uri_params.target.as_str() {
-            "singbox" | "clash" => match (1..151).contains(&uri_params.node_count) {
+        let page_size = match PROFILE_TARGETS.contains(&uri_params.target.as_str()) {
+            true => match (1..151).contains(&uri_params.node_count) {
                 true => uri_params.node_count,
                 false => 50,
             },
-            _ => uri_params.node_count,
+            false => uri_params.node_count,
+        };
+        // token限制每次订阅最多返回的节点数（每个地址至少生成一个节点，超过限制的地址不需要读取）
+        let page_size = match uri_params.node_limit {
+            0 => page_size,
+            limit => page_size.min(limit),
         };
 
         // 将 Vec<MyData> 转换为 Vec<Vec<MyData>>
@@ -83,171 +108,1156 @@ pub fn get_vec_data(uri_params: Params) -> Vec<Vec<MyData>> {
             .chunks(page_size)
             .map(|chunk| chunk.to_vec())
             .collect();
-        return paginated_data;
+        Ok(paginated_data)
     }
+}
 
-    return Vec::new();
+/// 订阅内容以及分页信息（用于响应头）
+#[derive(Default)]
+pub struct Subscription {
+    pub body: String,
+    pub page: usize,
+    pub total_pages: usize,     // 总页数
+    pub total_addresses: usize, // 所有页的地址数（过滤后的数据）
+    pub node_count: usize,      // 本页生成的节点数
 }
 
 /// 分拣数据以及创建订阅内容
 pub fn sorting_data_and_build_subscribe(
     all_proxies_yaml: YamlValue,
-    uri_params: Params,
-    clash_template: &str,
-    singbox_template: &str,
-) -> String {
-    let paginated_data = get_vec_data(uri_params.clone());
+    mut uri_params: Params,
+    templates: &HashMap<&str, &str>,
+    server_config: &ServerConfig,
+) -> Result<Subscription, AppError> {
+    if all_proxies_yaml
+        .as_sequence()
+        .is_none_or(|seq| seq.is_empty())
+    {
+        return Err(AppError::Internal("config.yaml中没有节点配置".to_string()));
+    }
+    let paginated_data = get_vec_data(uri_params.clone(), server_config)?;
+    let mut subscription = Subscription {
+        page: uri_params.page,
+        total_pages: paginated_data.len(),
+        total_addresses: paginated_data.iter().map(|page| page.len()).sum(),
+        ..Default::default()
+    };
+
+    // token限制了节点数时不能分页，只返回第一页（最多node_limit个节点）
+    if uri_params.node_limit > 0 {
+        if uri_params.page > 1 {
+            return Err(AppError::Forbidden(format!(
+                "这个token每次订阅最多返回{}个节点，不能使用page参数",
+                uri_params.node_limit
+            )));
+        }
+        subscription.total_pages = subscription.total_pages.min(1);
+        subscription.total_addresses = paginated_data.first().map_or(0, |page| page.len());
+    }
 
     match paginated_data.get(uri_params.page - 1) {
         Some(page_data) => {
-            // 没有数据，就返回空字符串
-            if page_data.is_empty() {
-                return String::new();
-            }
-
             // 下面的代码块，通过不同的转换，获取节点名称和节点配置或v2ray链接
-            let mut proxy_name_vec = Vec::new();
+            let mut proxy_metas: Vec<ProxyMeta> = Vec::new();
             let mut nodes_vec = Vec::new();
-            for item in page_data {
+            let mut converter = convert::SubConverter::new(&all_proxies_yaml, &uri_params);
+            // token限制了节点数时，生成的节点（包括expandports、pairing=cartesian多生成的节点）达到限制就不再继续
+            let limit_reached =
+                |count: usize| uri_params.node_limit > 0 && count >= uri_params.node_limit;
+            'items: for item in page_data {
                 let csv_alias = item.alias.clone().unwrap_or("".to_string());
                 let csv_addr = item.addr.clone();
                 let csv_port = item.port.unwrap_or(uri_params.default_port);
-                let (proxy_name, node) = convert::subconvert(
-                    csv_alias,
-                    csv_addr,
-                    csv_port,
-                    all_proxies_yaml.clone(),
-                    uri_params.target.clone(),
-                    uri_params.proxy_type.clone(),
-                    uri_params.tls_mode.clone(),
-                    uri_params.userid.clone(),
-                    &HTTP_PORTS,
-                    &HTTPS_PORTS,
-                );
-                if !node.is_empty() && !nodes_vec.contains(&node) {
-                    nodes_vec.push(node);
-                }
-                if !proxy_name.is_empty()
-                    && vec!["clash", "singbox"].contains(&uri_params.target.as_str())
-                    && !proxy_name_vec.contains(&proxy_name)
-                {
-                    proxy_name_vec.push(proxy_name);
+                for (mut meta, node) in converter.subconvert(csv_alias, csv_addr, csv_port) {
+                    if limit_reached(nodes_vec.len()) {
+                        break 'items;
+                    }
+                    // 数据中心、国家等字段用于代理组的筛选
+                    meta.colo = item.colo.clone().unwrap_or_default();
+                    meta.loc = item.loc.clone().unwrap_or_default();
+                    meta.region = item.region.clone().unwrap_or_default();
+                    meta.city = item.city.clone().unwrap_or_default();
+                    if !node.is_empty() && !nodes_vec.contains(&node) {
+                        nodes_vec.push(node);
+                    }
+                    if !meta.name.is_empty()
+                        && PROFILE_TARGETS.contains(&uri_params.target.as_str())
+                        && !proxy_metas.iter().any(|x| x.name == meta.name)
+                    {
+                        proxy_metas.push(meta);
+                    }
                 }
             }
 
-            // 防止没有nodes_vec数据
+            // 转换目标不支持而跳过的节点配置，没有节点时作为错误原因，否则注释在配置文件中
+            let skipped = converter.skipped;
+            // 所有节点配置都不符合id、type等参数的要求，或者不支持转换为这个目标
+            if nodes_vec.is_empty() && !skipped.is_empty() {
+                return Err(AppError::NotFound(format!(
+                    "没有可以转换为{}的节点配置，{}",
+                    uri_params.target,
+                    skipped.join("；")
+                )));
+            }
             if nodes_vec.is_empty() {
-                return String::new();
+                return Err(AppError::NotFound(
+                    "没有符合条件的节点配置，请检查id、type、tls、target等参数".to_string(),
+                ));
             }
+            subscription.node_count = nodes_vec.len();
 
             // 前面获取到数据后，开始构建完整的配置文件订阅（或分享链接订阅）
-            let full_subscribe = build_full_subscribe(
-                uri_params.target,
-                uri_params.template,
-                proxy_name_vec,
-                nodes_vec,
-                clash_template,
-                singbox_template,
-            );
-
-            full_subscribe
-        }
-        None => {
-            // 如果没有数据，就返回空字符串
-            return String::new();
+            // template=false&minimal=true时，使用内置的最小配置作为模板
+            let template = match (uri_params.template, uri_params.minimal) {
+                (true, _) if templates.contains_key(uri_params.target.as_str()) => {
+                    template::load_template(
+                        &uri_params.target,
+                        &uri_params.template_name,
+                        templates,
+                        server_config,
+                    )?
+                }
+                (false, true) => template::Template {
+                    content: template::minimal_template(&uri_params.target).to_string(),
+                    overrides: Vec::new(),
+                },
+                _ => template::Template::default(), // v2ray、clash-provider不使用模板
+            };
+            // 叠加模板中的覆盖设置先应用，订阅链接中的覆盖参数可以再修改它们
+            uri_params.overrides.splice(0..0, template.overrides);
+            subscription.body =
+                build_full_subscribe(&uri_params, proxy_metas, nodes_vec, &template.content)?;
+            if TEXT_TARGETS.contains(&uri_params.target.as_str()) && !skipped.is_empty() {
+                subscription.body =
+                    add_skipped_comment(&subscription.body, &uri_params.target, &skipped);
+            }
+            Ok(subscription)
         }
+        None => Err(AppError::NotFound(format!(
+            "第{}页超出范围，共{}页",
+            uri_params.page, subscription.total_pages
+        ))),
     }
 }
 
+// 文本格式的配置文件，可以用#注释说明跳过了哪些节点配置
+const TEXT_TARGETS: [&str; 4] = ["surge", "surfboard", "quanx", "loon"];
+
+// 在配置文件的开头（#!MANAGED-CONFIG等#!开头的行之后）注释跳过的节点配置及原因
+fn add_skipped_comment(body: &str, target: &str, skipped: &[String]) -> String {
+    let mut lines: Vec<String> = body.lines().map(|line| line.to_string()).collect();
+    let position = lines
+        .iter()
+        .position(|line| !line.starts_with("#!"))
+        .unwrap_or(lines.len());
+    let mut comment = vec![format!("# 以下节点配置不支持转换为{}，已跳过：", target)];
+    comment.extend(skipped.iter().map(|reason| format!("#   {}", reason)));
+    lines.splice(position..position, comment);
+    let mut output = lines.join("\n");
+    if body.ends_with('\n') {
+        output.push('\n');
+    }
+    output
+}
+
 /// 将生成的nodes_vec节点信息，构建完整的订阅（或分享链接订阅）
 fn build_full_subscribe(
-    target: String,
-    enable_template: bool,
-    proxy_name_vec: Vec<String>,
+    uri_params: &Params,
+    proxy_metas: Vec<ProxyMeta>,
     nodes_vec: Vec<String>,
-    clash_template: &str,
-    singbox_template: &str,
-) -> String {
+    content: &str,
+) -> Result<String, AppError> {
+    let proxy_name_vec: Vec<String> = proxy_metas.iter().map(|meta| meta.name.clone()).collect();
+    let enable_template = uri_params.template || (uri_params.minimal && !content.is_empty());
     let mut html_body = String::new();
-    match target.as_str() {
-        "clash" => {
-            match enable_template {
+    match uri_params.target.as_str() {
+        "clash" | "clash-provider" => {
+            let proxies: Vec<YamlValue> = nodes_vec
+                .iter()
+                .filter_map(|json_str| serde_yaml::from_str(json_str).ok())
+                .collect();
+            // proxy-providers只需要proxies列表，不使用模板
+            match enable_template && uri_params.target == "clash" {
                 true => {
-                    // 读取模板文件
-                    let content: String = std::fs::read_to_string(clash_template).unwrap();
-                    // 替换模板文件中的内容
-                    if !proxy_name_vec.is_empty() && !content.is_empty() {
-                        html_body = PROXYIES_NAME_REGEX
-                            .replace_all(&content, &nodes_vec.join("\n"))
-                            .replace(
-                                "      - 127.0.0.1:1080",
-                                &proxy_name_vec
-                                    .clone()
-                                    .iter_mut()
-                                    .map(|name| format!("      - {}", name))
-                                    .collect::<Vec<String>>()
-                                    .join("\n"),
-                            );
+                    let mut clash_config: YamlValue =
+                        serde_yaml::from_str(content).unwrap_or_default();
+                    if !clash_config.is_mapping() {
+                        return Err(invalid_template("clash"));
+                    }
+                    if !proxy_metas.is_empty() {
+                        overrides::apply_clash_overrides(&mut clash_config, &uri_params.overrides)?;
+                        merge_clash_template(
+                            &mut clash_config,
+                            proxies,
+                            &proxy_metas,
+                            &uri_params.group_by,
+                        );
+                        html_body = serde_yaml::to_string(&clash_config).unwrap_or_default();
                     }
                 }
                 false => {
-                    html_body = format!("proxies:\n{}", nodes_vec.join("\n"));
+                    let mut provider = serde_yaml::Mapping::new();
+                    provider.insert(YamlValue::from("proxies"), YamlValue::Sequence(proxies));
+                    html_body = serde_yaml::to_string(&provider).unwrap_or_default();
                 }
             }
         }
         "singbox" => {
+            let sb_version = uri_params.singbox_version;
             match enable_template {
                 true => {
-                    let content = std::fs::read_to_string(singbox_template).unwrap();
                     // 读取模板文件以及解析为JSON
-                    let singbox_json: JsonValue =
-                        serde_json::from_str(&content).unwrap_or_default();
+                    let singbox_json: JsonValue = serde_json::from_str(content).unwrap_or_default();
+                    if !singbox_json.is_object() {
+                        return Err(invalid_template("sing-box"));
+                    }
                     // 运用插入/retain()等操作修改模板文件的内容
-                    if !proxy_name_vec.is_empty() && singbox_json.is_object() {
+                    if !proxy_name_vec.is_empty() {
                         let mut singbox_config = singbox_json.clone();
+                        overrides::apply_singbox_overrides(
+                            &mut singbox_config,
+                            &uri_params.overrides,
+                        )?;
+                        // 模板是旧版的写法，按客户端的版本迁移
+                        singbox::migrate_singbox_config(
+                            &mut singbox_config,
+                            sb_version.unwrap_or(0),
+                        )?;
+                        let domain_resolver = singbox_config["route"]["default_domain_resolver"]
+                            .as_str()
+                            .unwrap_or_default()
+                            .to_string();
+                        let node_outbounds: Vec<JsonValue> = nodes_vec
+                            .iter()
+                            .map(|json_str| {
+                                let mut outbound =
+                                    serde_json::from_str(json_str).unwrap_or_default();
+                                singbox::migrate_singbox_outbound(
+                                    &mut outbound,
+                                    sb_version,
+                                    &domain_resolver,
+                                );
+                                outbound
+                            })
+                            .collect();
                         if let Some(outbounds) = singbox_config["outbounds"].as_array_mut() {
-                            // 将节点插入到outbounds中
-                            for json_str in &nodes_vec {
-                                let parsed_json =
-                                    serde_json::from_str(json_str).expect("Failed to parse JSON");
-                                outbounds.insert(2, parsed_json); // 插入到第3个位置
-                            }
-                            outbounds.iter_mut().for_each(|item| {
-                                if let Some(obj) = item.as_object_mut() {
-                                    obj.get_mut("outbounds")
-                                        .and_then(JsonValue::as_array_mut)
-                                        .map(|inside_outbounds| {
-                                            // 使用 retain 方法来过滤掉 "{all}"
-                                            inside_outbounds
-                                                .retain(|x| x.as_str() != Some("{all}"));
-                                            // 添加 proxy_name 到内层的 outbounds 中
-                                            inside_outbounds.extend(
-                                                proxy_name_vec
-                                                    .iter()
-                                                    .map(|s| JsonValue::String(s.clone())),
-                                            );
-                                        });
-                                }
-                            });
+                            // 按placement参数将节点（保持原有的顺序）放到outbounds中
+                            let position = match uri_params.placement.strip_prefix("after:") {
+                                Some(tag) => match outbounds.iter().position(|x| x["tag"] == tag) {
+                                    Some(index) => index + 1,
+                                    None => {
+                                        tracing::warn!(
+                                            "sing-box模板中没有{}出站，节点已追加到最后",
+                                            tag
+                                        );
+                                        outbounds.len()
+                                    }
+                                },
+                                None => outbounds.len(),
+                            };
+                            outbounds.splice(position..position, node_outbounds);
                         }
-                        html_body = serde_json::to_string_pretty(&singbox_config).unwrap();
+                        add_singbox_groupby(
+                            &mut singbox_config,
+                            &uri_params.group_by,
+                            &proxy_metas,
+                        );
+                        expand_singbox_groups(&mut singbox_config, &proxy_metas);
+                        html_body =
+                            serde_json::to_string_pretty(&singbox_config).unwrap_or_default();
                     }
                 }
                 false => {
                     let mut outbounds = json!({"outbounds": []});
                     if let Some(array) = outbounds["outbounds"].as_array_mut() {
                         nodes_vec.iter().for_each(|name| {
-                            array.push(serde_json::from_str(name).unwrap());
+                            let mut outbound = serde_json::from_str(name).unwrap_or_default();
+                            singbox::migrate_singbox_outbound(&mut outbound, sb_version, "");
+                            array.push(outbound);
                         });
                     }
-                    html_body = serde_json::to_string_pretty(&outbounds).unwrap();
+                    html_body = serde_json::to_string_pretty(&outbounds).unwrap_or_default();
                 }
             }
         }
+        "xray" => {
+            // 节点的outbound在前，分片用的freedom outbound（按tag去重）在后
+            let mut node_outbounds: Vec<JsonValue> = Vec::new();
+            let mut helper_outbounds: Vec<JsonValue> = Vec::new();
+            for json_str in &nodes_vec {
+                match serde_json::from_str(json_str).unwrap_or_default() {
+                    JsonValue::Array(items) => {
+                        let mut items = items.into_iter();
+                        node_outbounds.extend(items.next());
+                        for helper in items {
+                            if !helper_outbounds.iter().any(|x| x["tag"] == helper["tag"]) {
+                                helper_outbounds.push(helper);
+                            }
+                        }
+                    }
+                    JsonValue::Null => {}
+                    outbound => node_outbounds.push(outbound),
+                }
+            }
+            match enable_template {
+                true => {
+                    let mut xray_config: JsonValue =
+                        serde_json::from_str(content).unwrap_or_default();
+                    if !xray_config.is_object() {
+                        return Err(invalid_template("xray"));
+                    }
+                    if !proxy_name_vec.is_empty() {
+                        // 节点插入到outbounds的最前面（xray默认使用第一个outbound）
+                        if let Some(outbounds) = xray_config["outbounds"].as_array_mut() {
+                            let template_outbounds = std::mem::take(outbounds);
+                            outbounds.extend(node_outbounds);
+                            outbounds.extend(template_outbounds);
+                            outbounds.extend(helper_outbounds);
+                        }
+                        // observatory、balancers的selector中的"{all}"替换为所有节点的tag
+                        replace_all_placeholder(&mut xray_config, &proxy_name_vec);
+                        html_body = serde_json::to_string_pretty(&xray_config).unwrap_or_default();
+                    }
+                }
+                false => {
+                    node_outbounds.extend(helper_outbounds);
+                    let outbounds = json!({ "outbounds": node_outbounds });
+                    html_body = serde_json::to_string_pretty(&outbounds).unwrap_or_default();
+                }
+            }
+        }
+        "surge" | "surfboard" | "quanx" | "loon" => match enable_template {
+            true => {
+                if content.trim().is_empty() {
+                    return Err(invalid_template(&uri_params.target));
+                }
+                if !proxy_name_vec.is_empty() {
+                    html_body = render_text_template(content, &nodes_vec, &proxy_name_vec);
+                }
+            }
+            false => {
+                let section = match uri_params.target.as_str() {
+                    "quanx" => "[server_local]",
+                    _ => "[Proxy]",
+                };
+                html_body = format!("{}\n{}", section, nodes_vec.join("\n"));
+            }
+        },
         _ => {
-            html_body = nodes_vec.join("\n"); // 视为v2ray订阅的分享链接
+            // 视为v2ray订阅的分享链接，客户端通常要求base64编码的订阅内容
+            let links = nodes_vec.join("\n");
+            html_body = match uri_params.encode.as_str() {
+                "raw" => links,
+                "base64url" => base64::encode_config(links.as_bytes(), base64::URL_SAFE),
+                _ => base64::encode(links.as_bytes()),
+            };
+        }
+    }
+
+    Ok(html_body)
+}
+
+// 模板文件不存在或者内容无效，属于服务器端的问题
+fn invalid_template(target: &str) -> AppError {
+    AppError::Internal(format!("{}的模板无效，请检查模板文件", target))
+}
+
+// 旧版clash模板中所有节点的占位符
+const CLASH_LEGACY_PLACEHOLDER: &str = "127.0.0.1:1080";
+
+/// 将节点合并到clash模板中：替换模板的proxies，展开proxy-groups中的筛选标记（{all}、{type:vless}、{regex:^HK}）
+fn merge_clash_template(
+    clash_config: &mut YamlValue,
+    proxies: Vec<YamlValue>,
+    proxy_metas: &[ProxyMeta],
+    group_by: &str,
+) {
+    let config = match clash_config.as_mapping_mut() {
+        Some(config) => config,
+        None => return,
+    };
+    config.insert(YamlValue::from("proxies"), YamlValue::Sequence(proxies));
+
+    let groups = match config
+        .get_mut("proxy-groups")
+        .and_then(YamlValue::as_sequence_mut)
+    {
+        Some(groups) => groups,
+        None => return,
+    };
+    for group in groups.iter_mut() {
+        if let Some(YamlValue::Sequence(members)) = group.get_mut("proxies") {
+            // 旧版模板使用"127.0.0.1:1080"作为所有节点的占位符，跟"{all}"一样处理
+            let names: Vec<String> = members
+                .iter()
+                .filter_map(|x| x.as_str())
+                .map(|x| match x.trim() {
+                    CLASH_LEGACY_PLACEHOLDER => "{all}".to_string(),
+                    _ => x.to_string(),
+                })
+                .collect();
+            *members = group::expand_group_members(&names, proxy_metas)
+                .into_iter()
+                .map(YamlValue::String)
+                .collect();
+        }
+    }
+    prune_empty_clash_groups(config);
+    let groups = match config
+        .get_mut("proxy-groups")
+        .and_then(YamlValue::as_sequence_mut)
+    {
+        Some(groups) => groups,
+        None => return,
+    };
+
+    // groupby：顶层选择组放到第一个select代理组的最前面，自动生成的代理组追加到最后
+    let grouped = group::group_proxies_by(group_by, proxy_metas);
+    if grouped.is_empty() {
+        return;
+    }
+    let first_select = groups
+        .iter_mut()
+        .find(|g| g.get("type").and_then(|v| v.as_str()) == Some("select"));
+    if let Some(YamlValue::Sequence(members)) = first_select.and_then(|g| g.get_mut("proxies")) {
+        members.insert(0, YamlValue::from(group::GROUPBY_SELECTOR_NAME));
+    }
+    let to_yaml_seq = |names: Vec<&String>| -> YamlValue {
+        YamlValue::Sequence(
+            names
+                .into_iter()
+                .map(|n| YamlValue::from(n.as_str()))
+                .collect(),
+        )
+    };
+    let mut selector = serde_yaml::Mapping::new();
+    selector.insert("name".into(), group::GROUPBY_SELECTOR_NAME.into());
+    selector.insert("type".into(), "select".into());
+    selector.insert(
+        "proxies".into(),
+        to_yaml_seq(grouped.iter().map(|(name, _)| name).collect()),
+    );
+    groups.push(YamlValue::Mapping(selector));
+    for (name, members) in &grouped {
+        let mut url_test = serde_yaml::Mapping::new();
+        url_test.insert("name".into(), name.as_str().into());
+        url_test.insert("type".into(), "url-test".into());
+        url_test.insert("url".into(), "http://www.gstatic.com/generate_204".into());
+        url_test.insert("interval".into(), 300.into());
+        url_test.insert("tolerance".into(), 50.into());
+        url_test.insert("proxies".into(), to_yaml_seq(members.iter().collect()));
+        groups.push(YamlValue::Mapping(url_test));
+    }
+}
+
+/// 删除筛选后没有节点的Clash代理组（mihomo不允许proxies为空，使用了proxy-providers的代理组除外），
+/// 以及其它代理组、规则对它们的引用，跟sing-box的处理一致
+fn prune_empty_clash_groups(config: &mut serde_yaml::Mapping) {
+    let groups = match config
+        .get_mut("proxy-groups")
+        .and_then(YamlValue::as_sequence_mut)
+    {
+        Some(groups) => groups,
+        None => return,
+    };
+    let group_name = |g: &YamlValue| g.get("name").and_then(|v| v.as_str()).map(String::from);
+
+    // 删除一个空的代理组后，引用它的代理组也可能变为空的，所以要循环处理
+    let mut removed_names: Vec<String> = Vec::new();
+    loop {
+        let empty_names: Vec<String> = groups
+            .iter()
+            .filter(|g| {
+                g.get("proxies")
+                    .and_then(|v| v.as_sequence())
+                    .is_some_and(|m| m.is_empty())
+                    && g.get("use").is_none()
+                    && g.get("include-all").and_then(|v| v.as_bool()) != Some(true)
+            })
+            .filter_map(group_name)
+            .collect();
+        if empty_names.is_empty() {
+            break;
         }
+        let is_empty_name = |x: &YamlValue| {
+            x.as_str()
+                .is_some_and(|n| empty_names.iter().any(|e| e == n))
+        };
+        groups.retain(|g| !g.get("name").is_some_and(is_empty_name));
+        for group in groups.iter_mut() {
+            if let Some(YamlValue::Sequence(members)) = group.get_mut("proxies") {
+                members.retain(|x| !is_empty_name(x));
+            }
+        }
+        removed_names.extend(empty_names);
+    }
+    if removed_names.is_empty() {
+        return;
+    }
+    tracing::warn!("clash代理组{}没有节点，已删除", removed_names.join("、"));
+
+    // 规则的写法为"类型,参数,代理组[,no-resolve]"或"MATCH,代理组"，引用了被删除代理组的规则也删除
+    if let Some(rules) = config.get_mut("rules").and_then(YamlValue::as_sequence_mut) {
+        rules.retain(|rule| {
+            !rule.as_str().is_some_and(|rule| {
+                rule.split(',')
+                    .skip(1)
+                    .any(|field| removed_names.iter().any(|r| r == field.trim()))
+            })
+        });
     }
+}
 
-    html_body
+/// groupby：顶层选择组放到第一个selector的最前面，自动生成的代理组放在最后一个代理组的后面
+fn add_singbox_groupby(singbox_config: &mut JsonValue, group_by: &str, proxy_metas: &[ProxyMeta]) {
+    let grouped = group::group_proxies_by(group_by, proxy_metas);
+    let outbounds = match singbox_config["outbounds"].as_array_mut() {
+        Some(outbounds) if !grouped.is_empty() => outbounds,
+        _ => return,
+    };
+    if let Some(first_selector) = outbounds.iter_mut().find(|x| x["type"] == "selector") {
+        if let Some(members) = first_selector["outbounds"].as_array_mut() {
+            members.insert(0, json!(group::GROUPBY_SELECTOR_NAME));
+        }
+    }
+    let group_names: Vec<&String> = grouped.iter().map(|(name, _)| name).collect();
+    let mut generated = vec![json!({
+        "tag": group::GROUPBY_SELECTOR_NAME,
+        "type": "selector",
+        "outbounds": group_names,
+    })];
+    for (name, members) in &grouped {
+        generated.push(json!({
+            "tag": name,
+            "type": "urltest",
+            "outbounds": members,
+            "url": "http://www.gstatic.com/generate_204",
+            "interval": "10m",
+            "tolerance": 50,
+        }));
+    }
+    let position = outbounds
+        .iter()
+        .rposition(|x| ["selector", "urltest"].contains(&x["type"].as_str().unwrap_or_default()))
+        .map_or(0, |index| index + 1);
+    outbounds.splice(position..position, generated);
+}
+
+/// 展开sing-box代理组（selector、urltest）中的筛选标记，并删除没有成员的代理组（sing-box不允许），
+/// 以及其它代理组、路由规则对它们的引用
+fn expand_singbox_groups(singbox_config: &mut JsonValue, proxy_metas: &[ProxyMeta]) {
+    let outbounds = match singbox_config["outbounds"].as_array_mut() {
+        Some(outbounds) => outbounds,
+        None => return,
+    };
+    for item in outbounds.iter_mut() {
+        if let Some(members) = item.get_mut("outbounds").and_then(JsonValue::as_array_mut) {
+            let names: Vec<String> = members
+                .iter()
+                .filter_map(|x| x.as_str().map(String::from))
+                .collect();
+            *members = group::expand_group_members(&names, proxy_metas)
+                .into_iter()
+                .map(JsonValue::String)
+                .collect();
+        }
+    }
+
+    // 删除一个空的代理组后，引用它的代理组也可能变为空的，所以要循环处理
+    let mut removed_tags: Vec<String> = Vec::new();
+    loop {
+        let empty_tags: Vec<String> = outbounds
+            .iter()
+            .filter(|x| x["outbounds"].as_array().is_some_and(|m| m.is_empty()))
+            .filter_map(|x| x["tag"].as_str().map(String::from))
+            .collect();
+        if empty_tags.is_empty() {
+            break;
+        }
+        let is_empty_tag = |x: &JsonValue| {
+            x.as_str()
+                .is_some_and(|t| empty_tags.iter().any(|e| e == t))
+        };
+        outbounds.retain(|x| !is_empty_tag(&x["tag"]));
+        for item in outbounds.iter_mut() {
+            if let Some(members) = item.get_mut("outbounds").and_then(JsonValue::as_array_mut) {
+                members.retain(|x| !is_empty_tag(x));
+            }
+            if let Some(obj) = item.as_object_mut() {
+                if obj.get("default").is_some_and(is_empty_tag) {
+                    obj.remove("default");
+                }
+            }
+        }
+        removed_tags.extend(empty_tags);
+    }
+    if removed_tags.is_empty() {
+        return;
+    }
+    tracing::warn!("sing-box代理组{}没有节点，已删除", removed_tags.join("、"));
+
+    let is_removed = |x: &JsonValue| {
+        x.as_str()
+            .is_some_and(|t| removed_tags.iter().any(|r| r == t))
+    };
+    if let Some(route) = singbox_config
+        .get_mut("route")
+        .and_then(JsonValue::as_object_mut)
+    {
+        if let Some(rules) = route.get_mut("rules").and_then(JsonValue::as_array_mut) {
+            rules.retain(|rule| !is_removed(&rule["outbound"]));
+        }
+        if route.get("final").is_some_and(is_removed) {
+            route.remove("final");
+        }
+    }
+}
+
+/// 将JSON中所有数组里的"{all}"替换为所有节点名称
+fn replace_all_placeholder(value: &mut JsonValue, proxy_name_vec: &[String]) {
+    match value {
+        JsonValue::Array(items) => {
+            if items.iter().any(|x| x.as_str() == Some("{all}")) {
+                items.retain(|x| x.as_str() != Some("{all}"));
+                items.extend(proxy_name_vec.iter().map(|s| JsonValue::String(s.clone())));
+            }
+            items
+                .iter_mut()
+                .for_each(|item| replace_all_placeholder(item, proxy_name_vec));
+        }
+        JsonValue::Object(map) => map
+            .values_mut()
+            .for_each(|item| replace_all_placeholder(item, proxy_name_vec)),
+        _ => {}
+    }
+}
+
+/// 将节点写入文本格式的模板（Surge、Surfboard、Quantumult X、Loon）：
+/// 单独一行的`{proxies}`替换为所有代理行，其它行中的`{all}`替换为逗号分隔的所有节点名称
+fn render_text_template(content: &str, nodes_vec: &[String], proxy_name_vec: &[String]) -> String {
+    let all_names = proxy_name_vec.join(", ");
+    content
+        .lines()
+        .map(|line| match line.trim() {
+            "{proxies}" => nodes_vec.join("\n"),
+            _ => line.replace("{all}", &all_names),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metas() -> Vec<ProxyMeta> {
+        ["US-LAX-1", "US-SJC-2", "JP-NRT-3"]
+            .iter()
+            .map(|name| ProxyMeta {
+                name: name.to_string(),
+                proxy_type: "vless".to_string(),
+                config_id: 1,
+                loc: name[..2].to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn group_members(config: &YamlValue, name: &str) -> Option<Vec<String>> {
+        config["proxy-groups"]
+            .as_sequence()?
+            .iter()
+            .find(|g| g["name"].as_str() == Some(name))
+            .map(|g| {
+                g["proxies"]
+                    .as_sequence()
+                    .unwrap()
+                    .iter()
+                    .map(|x| x.as_str().unwrap().to_string())
+                    .collect()
+            })
+    }
+
+    fn build_links(encode: &str, links: &[&str]) -> String {
+        let params = Params {
+            target: "v2ray".to_string(),
+            encode: encode.to_string(),
+            ..Default::default()
+        };
+        let nodes = links.iter().map(|link| link.to_string()).collect();
+        build_full_subscribe(&params, Vec::new(), nodes, "").unwrap()
+    }
+
+    #[test]
+    fn node_limit_truncates_in_every_pairing_mode() {
+        let dir = std::env::temp_dir().join(format!("cfwks-limit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data_file = dir.join("ip.txt");
+        std::fs::write(&data_file, "1.1.1.1:443\n2.2.2.2:443\n3.3.3.3:443\n").unwrap();
+        let configs: YamlValue = serde_yaml::from_str(
+            "- { name: a, type: trojan, server: a.example.com, port: 443, password: pw, tls: true }\n\
+             - { name: b, type: trojan, server: b.example.com, port: 443, password: pw, tls: true }",
+        )
+        .unwrap();
+        let server_config = ServerConfig::default();
+        for (expand_ports, pairing, target) in [
+            (false, "random", "v2ray"),
+            (true, "random", "v2ray"),
+            (false, "cartesian", "v2ray"),
+            (true, "cartesian", "clash"),
+        ] {
+            let params = Params {
+                target: target.to_string(),
+                encode: "raw".to_string(),
+                data_source: data_file.to_string_lossy().to_string(),
+                proxy_type: "all".to_string(),
+                tls_mode: "all".to_string(),
+                http_ports: vec![80, 8080],
+                https_ports: vec![443, 2053, 8443],
+                port_mode: "auto".to_string(),
+                expand_ports,
+                pairing: pairing.to_string(),
+                seed: Some(1),
+                node_count: 10,
+                page: 1,
+                node_limit: 2,
+                ..Default::default()
+            };
+            let case = format!("{} {} {}", expand_ports, pairing, target);
+            let subscription = sorting_data_and_build_subscribe(
+                configs.clone(),
+                params,
+                &HashMap::new(),
+                &server_config,
+            )
+            .expect(&case);
+            assert_eq!(subscription.node_count, 2, "{}", case);
+            let nodes = match target {
+                "clash" => {
+                    let provider: YamlValue = serde_yaml::from_str(&subscription.body).unwrap();
+                    provider["proxies"].as_sequence().unwrap().len()
+                }
+                _ => subscription.body.lines().count(),
+            };
+            assert_eq!(nodes, 2, "{}", case);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn skipped_configs_are_explained() {
+        let dir = std::env::temp_dir().join(format!("cfwks-skipped-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data_file = dir.join("ip.txt");
+        std::fs::write(&data_file, "1.1.1.1:443\n2.2.2.2:443\n").unwrap();
+        let configs: YamlValue = serde_yaml::from_str(
+            "- { name: v, type: vless, server: a.example.com, port: 443, uuid: id, tls: true }\n\
+             - { name: t, type: trojan, server: b.example.com, port: 443, password: pw, tls: true }",
+        )
+        .unwrap();
+        let build = |target: &str, userid: u8, template: bool| {
+            let params = Params {
+                target: target.to_string(),
+                userid,
+                template,
+                data_source: data_file.to_string_lossy().to_string(),
+                proxy_type: "all".to_string(),
+                tls_mode: "all".to_string(),
+                https_ports: vec![443],
+                port_mode: "auto".to_string(),
+                pairing: "random".to_string(),
+                seed: Some(1),
+                node_count: 10,
+                page: 1,
+                ..Default::default()
+            };
+            let templates = HashMap::from([("surge", "template/surge.conf")]);
+            sorting_data_and_build_subscribe(
+                configs.clone(),
+                params,
+                &templates,
+                &ServerConfig::default(),
+            )
+        };
+
+        let skipped = "第1个节点配置v：不支持vless协议";
+        for template in [true, false] {
+            let body = build("surge", 0, template).unwrap().body;
+            let expected = format!(
+                "# 以下节点配置不支持转换为surge，已跳过：\n#   {}\n",
+                skipped
+            );
+            assert!(body.starts_with(&expected), "{}", body);
+            assert_eq!(body.matches("= trojan,").count(), 2);
+        }
+        // 只有不支持的节点配置时返回404，并说明原因
+        let error = build("surge", 1, false).err().unwrap();
+        assert!(
+            matches!(&error, AppError::NotFound(m) if m.ends_with(skipped)),
+            "{}",
+            error
+        );
+        // 没有跳过节点配置时不添加注释
+        let body = build("surge", 2, false).unwrap().body;
+        assert!(!body.contains('#'));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn skipped_comment_stays_after_managed_config_line() {
+        let skipped = ["第1个节点配置v：不支持vless协议".to_string()];
+        assert_eq!(
+            add_skipped_comment("#!MANAGED-CONFIG http://x\n[General]\n", "surge", &skipped),
+            "#!MANAGED-CONFIG http://x\n# 以下节点配置不支持转换为surge，已跳过：\n#   第1个节点配置v：不支持vless协议\n[General]\n"
+        );
+    }
+
+    #[test]
+    fn v2ray_links_are_encoded() {
+        let links = [
+            "vless://id@1.1.1.1:443?a=b#n1",
+            "trojan://pw@2.2.2.2:443?c=d#n2>?",
+        ];
+        let raw = links.join("\n");
+        assert_eq!(build_links("raw", &links), raw);
+        assert_eq!(build_links("base64", &links), base64::encode(&raw));
+        let url_safe = build_links("base64url", &links);
+        assert_eq!(url_safe, base64::encode_config(&raw, base64::URL_SAFE));
+        assert!(!url_safe.contains('+') && !url_safe.contains('/'));
+        assert_eq!(
+            base64::decode_config(&url_safe, base64::URL_SAFE).unwrap(),
+            raw.as_bytes()
+        );
+    }
+
+    #[test]
+    fn clash_provider_is_plain_proxies_list() {
+        let params = Params {
+            target: "clash-provider".to_string(),
+            template: true,
+            ..Default::default()
+        };
+        let nodes = vec![
+            r#"{"name": "n1", "type": "vless", "server": "1.1.1.1", "port": 443}"#.to_string(),
+            r#"{"name": "n2", "type": "trojan", "server": "2.2.2.2", "port": 443}"#.to_string(),
+        ];
+        // 模板（这里不是有效的Clash配置）不会被使用
+        let output = build_full_subscribe(&params, metas(), nodes, "not: [a template").unwrap();
+        assert!(output.starts_with("proxies:\n- name: n1\n"));
+        let provider: YamlValue = serde_yaml::from_str(&output).unwrap();
+        let provider = provider.as_mapping().unwrap();
+        assert_eq!(provider.len(), 1);
+        let proxies = provider["proxies"].as_sequence().unwrap();
+        assert_eq!(proxies.len(), 2);
+        assert_eq!(proxies[1]["type"].as_str(), Some("trojan"));
+        assert_eq!(proxies[1]["port"].as_u64(), Some(443));
+    }
+
+    #[test]
+    fn xray_template_puts_nodes_first_and_helpers_last() {
+        let params = Params {
+            target: "xray".to_string(),
+            template: true,
+            ..Default::default()
+        };
+        let fragment = r#"{"tag": "fragment:1", "protocol": "freedom"}"#;
+        let nodes = vec![
+            format!(r#"[{{"tag": "n1"}}, {}]"#, fragment),
+            format!(r#"[{{"tag": "n2"}}, {}]"#, fragment),
+            r#"{"tag": "n3"}"#.to_string(),
+        ];
+        let metas = ["n1", "n2", "n3"]
+            .iter()
+            .map(|name| ProxyMeta {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let template = r#"{
+            "outbounds": [{"tag": "direct", "protocol": "freedom"}],
+            "observatory": {"subjectSelector": ["{all}"]}
+        }"#;
+        let output = build_full_subscribe(&params, metas, nodes, template).unwrap();
+        let config: JsonValue = serde_json::from_str(&output).unwrap();
+        let tags: Vec<&str> = config["outbounds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|outbound| outbound["tag"].as_str().unwrap())
+            .collect();
+        assert_eq!(tags, ["n1", "n2", "n3", "direct", "fragment:1"]);
+        assert_eq!(
+            config["observatory"]["subjectSelector"],
+            json!(["n1", "n2", "n3"])
+        );
+    }
+
+    fn singbox_tags(config: &JsonValue, key: &str) -> Vec<String> {
+        config[key]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| {
+                x["tag"]
+                    .as_str()
+                    .unwrap_or_else(|| x.as_str().unwrap())
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn singbox_nodes_keep_order_at_placement() {
+        let nodes: Vec<String> = metas()
+            .iter()
+            .map(|meta| json!({ "type": "vless", "tag": meta.name }).to_string())
+            .collect();
+        let template = json!({
+            "outbounds": [
+                { "type": "selector", "tag": "proxy", "outbounds": ["{all}"] },
+                { "type": "direct", "tag": "direct" }
+            ]
+        })
+        .to_string();
+        for (placement, expected) in [
+            (
+                "append",
+                ["proxy", "direct", "US-LAX-1", "US-SJC-2", "JP-NRT-3"],
+            ),
+            (
+                "after:proxy",
+                ["proxy", "US-LAX-1", "US-SJC-2", "JP-NRT-3", "direct"],
+            ),
+            (
+                "after:missing",
+                ["proxy", "direct", "US-LAX-1", "US-SJC-2", "JP-NRT-3"],
+            ),
+        ] {
+            let params = Params {
+                target: "singbox".to_string(),
+                template: true,
+                placement: placement.to_string(),
+                ..Default::default()
+            };
+            let output = build_full_subscribe(&params, metas(), nodes.clone(), &template).unwrap();
+            let config: JsonValue = serde_json::from_str(&output).unwrap();
+            assert_eq!(
+                singbox_tags(&config, "outbounds"),
+                expected,
+                "{}",
+                placement
+            );
+        }
+    }
+
+    #[test]
+    fn singbox_groups_are_expanded_and_empty_ones_pruned() {
+        let mut config = json!({
+            "outbounds": [
+                { "type": "selector", "tag": "proxy", "outbounds": ["auto", "hk", "only-hk", "direct"], "default": "hk" },
+                { "type": "urltest", "tag": "auto", "outbounds": ["{loc:us}", "{regex:NRT}", "{all}"] },
+                { "type": "urltest", "tag": "hk", "outbounds": ["{loc:HK}"] },
+                { "type": "selector", "tag": "only-hk", "outbounds": ["hk"] },
+                { "type": "direct", "tag": "direct" }
+            ],
+            "route": {
+                "rules": [
+                    { "domain_suffix": ["hk"], "outbound": "only-hk" },
+                    { "domain_suffix": ["jp"], "outbound": "auto" }
+                ],
+                "final": "hk"
+            }
+        });
+        expand_singbox_groups(&mut config, &metas());
+        assert_eq!(
+            singbox_tags(&config, "outbounds"),
+            ["proxy", "auto", "direct"]
+        );
+        assert_eq!(
+            config["outbounds"][0]["outbounds"],
+            json!(["auto", "direct"])
+        );
+        assert!(config["outbounds"][0].get("default").is_none());
+        assert_eq!(
+            config["outbounds"][1]["outbounds"],
+            json!(["US-LAX-1", "US-SJC-2", "JP-NRT-3"])
+        );
+        assert!(config["outbounds"][2].get("outbounds").is_none());
+        assert_eq!(
+            config["route"],
+            json!({ "rules": [{ "domain_suffix": ["jp"], "outbound": "auto" }] })
+        );
+    }
+
+    #[test]
+    fn minimal_clash_profile_is_complete() {
+        let params = Params {
+            target: "clash".to_string(),
+            minimal: true,
+            ..Default::default()
+        };
+        let nodes = metas()
+            .iter()
+            .map(|meta| {
+                json!({ "name": meta.name, "type": "vless", "server": "1.1.1.1", "port": 443 })
+                    .to_string()
+            })
+            .collect();
+        let content = template::minimal_template("clash");
+        let output = build_full_subscribe(&params, metas(), nodes, content).unwrap();
+        let config: YamlValue = serde_yaml::from_str(&output).unwrap();
+        assert_eq!(config["proxies"].as_sequence().unwrap().len(), 3);
+        let all = ["US-LAX-1", "US-SJC-2", "JP-NRT-3"];
+        assert_eq!(group_members(&config, "♻️ 自动选择").unwrap(), all);
+        assert_eq!(
+            group_members(&config, "🔰 节点选择").unwrap(),
+            ["♻️ 自动选择", "US-LAX-1", "US-SJC-2", "JP-NRT-3", "DIRECT"]
+        );
+        let rules = config["rules"].as_sequence().unwrap();
+        assert_eq!(rules.len(), 1);
+        let target = rules[0].as_str().unwrap().rsplit(',').next().unwrap();
+        assert!(group_members(&config, target).is_some());
+    }
+
+    #[test]
+    fn minimal_singbox_profile_resolves() {
+        let nodes: Vec<String> = metas()
+            .iter()
+            .map(|meta| json!({ "type": "vless", "tag": meta.name }).to_string())
+            .collect();
+        let content = template::minimal_template("singbox");
+        for version in [None, Some(11), Some(12)] {
+            let params = Params {
+                target: "singbox".to_string(),
+                minimal: true,
+                singbox_version: version,
+                ..Default::default()
+            };
+            let output = build_full_subscribe(&params, metas(), nodes.clone(), content).unwrap();
+            let config: JsonValue = serde_json::from_str(&output).unwrap();
+            let tags = singbox_tags(&config, "outbounds");
+            assert_eq!(
+                tags,
+                ["proxy", "auto", "direct", "US-LAX-1", "US-SJC-2", "JP-NRT-3"]
+            );
+            // 代理组引用的出站和route.final都必须存在
+            for outbound in config["outbounds"].as_array().unwrap() {
+                for member in outbound["outbounds"].as_array().into_iter().flatten() {
+                    let member = member.as_str().unwrap().to_string();
+                    assert!(tags.contains(&member), "{:?} {}", version, member);
+                }
+            }
+            let route_final = config["route"]["final"].as_str().unwrap().to_string();
+            assert!(tags.contains(&route_final), "{:?}", version);
+        }
+    }
+
+    #[test]
+    fn clash_template_expands_markers_and_legacy_placeholder() {
+        let mut config: YamlValue = serde_yaml::from_str(
+            r#"
+proxy-groups:
+  - { name: select, type: select, proxies: [auto, DIRECT, "127.0.0.1:1080"] }
+  - { name: auto, type: url-test, proxies: ["{loc:us}"] }
+"#,
+        )
+        .unwrap();
+        merge_clash_template(&mut config, Vec::new(), &metas(), "");
+        assert_eq!(
+            group_members(&config, "select").unwrap(),
+            ["auto", "DIRECT", "US-LAX-1", "US-SJC-2", "JP-NRT-3"]
+        );
+        assert_eq!(
+            group_members(&config, "auto").unwrap(),
+            ["US-LAX-1", "US-SJC-2"]
+        );
+    }
+
+    #[test]
+    fn clash_groupby_adds_selector_and_url_test_groups() {
+        let template = "proxy-groups:\n  - { name: select, type: select, proxies: [DIRECT] }\n";
+        let mut config: YamlValue = serde_yaml::from_str(template).unwrap();
+        merge_clash_template(&mut config, Vec::new(), &metas(), "loc");
+        assert_eq!(
+            group_members(&config, "select").unwrap(),
+            [group::GROUPBY_SELECTOR_NAME, "DIRECT"]
+        );
+        assert_eq!(
+            group_members(&config, group::GROUPBY_SELECTOR_NAME).unwrap(),
+            ["📍 US", "📍 JP"]
+        );
+        assert_eq!(
+            group_members(&config, "📍 US").unwrap(),
+            ["US-LAX-1", "US-SJC-2"]
+        );
+        assert_eq!(group_members(&config, "📍 JP").unwrap(), ["JP-NRT-3"]);
+
+        // 不使用groupby时模板保持原样
+        let mut config: YamlValue = serde_yaml::from_str(template).unwrap();
+        merge_clash_template(&mut config, Vec::new(), &metas(), "");
+        assert_eq!(config["proxy-groups"].as_sequence().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn singbox_groupby_is_placed_after_last_group() {
+        let mut config = json!({
+            "outbounds": [
+                { "type": "selector", "tag": "proxy", "outbounds": ["auto"] },
+                { "type": "urltest", "tag": "auto", "outbounds": ["{all}"] },
+                { "type": "direct", "tag": "direct" }
+            ]
+        });
+        add_singbox_groupby(&mut config, "loc", &metas());
+        assert_eq!(
+            singbox_tags(&config, "outbounds"),
+            [
+                "proxy",
+                "auto",
+                group::GROUPBY_SELECTOR_NAME,
+                "📍 US",
+                "📍 JP",
+                "direct"
+            ]
+        );
+        assert_eq!(
+            config["outbounds"][0]["outbounds"],
+            json!([group::GROUPBY_SELECTOR_NAME, "auto"])
+        );
+        assert_eq!(config["outbounds"][3]["type"], "urltest");
+        assert_eq!(
+            config["outbounds"][3]["outbounds"],
+            json!(["US-LAX-1", "US-SJC-2"])
+        );
+
+        let mut unchanged = config.clone();
+        add_singbox_groupby(&mut unchanged, "", &metas());
+        assert_eq!(unchanged, config);
+    }
+
+    #[test]
+    fn clash_template_prunes_empty_groups_and_references() {
+        let mut config: YamlValue = serde_yaml::from_str(
+            r#"
+proxy-groups:
+  - { name: select, type: select, proxies: [hk, outer, DIRECT] }
+  - { name: hk, type: url-test, proxies: ["{regex:^HK}"] }
+  - { name: outer, type: select, proxies: [hk] }
+  - { name: provider, type: select, use: [cfwks], proxies: [] }
+rules:
+  - DOMAIN-SUFFIX,hk.example.com,hk
+  - DOMAIN-SUFFIX,outer.example.com,outer,no-resolve
+  - MATCH,select
+"#,
+        )
+        .unwrap();
+        merge_clash_template(&mut config, Vec::new(), &metas(), "");
+        assert_eq!(group_members(&config, "select").unwrap(), ["DIRECT"]);
+        assert!(group_members(&config, "hk").is_none());
+        assert!(group_members(&config, "outer").is_none());
+        assert!(group_members(&config, "provider").is_some());
+        assert_eq!(
+            config["rules"],
+            serde_yaml::from_str::<YamlValue>(r#"["MATCH,select"]"#).unwrap()
+        );
+    }
 }
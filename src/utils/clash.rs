@@ -1,3 +1,4 @@
+use super::tls::TlsOptions;
 use serde_yaml::Value as YamlValue;
 
 // 原来就是clash配置的，直接修改对应的值即可
@@ -17,6 +18,28 @@ pub fn build_clash_yaml(
         if let Some(port_value) = map.get_mut("port") {
             *port_value = YamlValue::Number(server_port.into());
         }
+        // 节点没有写tls字段时，clash默认不开启tls，这里写入跟其它订阅一致的判断结果
+        let node = YamlValue::Mapping(map.clone());
+        match map.get("type").and_then(|v| v.as_str()) {
+            Some("vless") if !map.contains_key("tls") => {
+                let tls_enabled = TlsOptions::from_yaml(&node, "vless").enabled;
+                map.insert(YamlValue::from("tls"), YamlValue::Bool(tls_enabled));
+            }
+            Some("ss") => {
+                if let Some(YamlValue::Mapping(plugin_opts)) = map.get_mut("plugin-opts") {
+                    if !plugin_opts.contains_key("tls") {
+                        let tls_enabled = TlsOptions::from_yaml(&node, "ss").enabled;
+                        plugin_opts.insert(YamlValue::from("tls"), YamlValue::Bool(tls_enabled));
+                    }
+                }
+            }
+            _ => {}
+        }
+        // tls-fragment是本工具自定义的字段，clash核心不认识
+        map.remove("tls-fragment");
+        if let Some(YamlValue::Mapping(plugin_opts)) = map.get_mut("plugin-opts") {
+            plugin_opts.remove("tls-fragment");
+        }
     }
     yaml_value
 }
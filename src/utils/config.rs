@@ -34,7 +34,7 @@ pub fn get_yaml_value<'a>(yaml: &'a YamlValue, keys: &[&str]) -> Option<&'a Yaml
             .as_mapping()?
             .iter()
             .find(|(k, _)| {
-                k.as_str().map_or(false, |k_str| k_str.to_lowercase() == key.to_lowercase())
+                k.as_str().is_some_and(|k_str| k_str.to_lowercase() == key.to_lowercase())
             })
             .map(|(_, v)| v)?;
     }
@@ -1,149 +1,544 @@
-use super::{clash, config::get_yaml_value, singbox, v2ray};
-use rand::{seq::SliceRandom, Rng};
+use super::{clash, group::ProxyMeta, loon, quanx, singbox, surge, tls::TlsOptions, v2ray, xray};
+use crate::Params;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 use serde_yaml::Value as YamlValue;
 
-pub fn subconvert(
-    csv_alias: String,
-    csv_addr: String,
-    mut port: u16,
-    yamlvalue: YamlValue,
-    uri_target: String,
-    uri_proxy_type: String,
-    uri_tls_mode: String,
-    uri_userid: u8,
-    http_ports: &[u16; 7],
-    https_ports: &[u16; 6],
-) -> (String, String) {
-    // 判断端口类型的闭包
-    let is_https_ports = move |port: u16| -> bool { https_ports.contains(&port) };
-    let is_http_ports = move |port: u16| -> bool { http_ports.contains(&port) };
-
-    let csv_remarks = match csv_alias.is_empty() {
-        true => String::new(),
-        false => format!("{} | ", csv_alias),
-    };
-    if let Some(sequence) = yamlvalue.clone().as_sequence_mut() {
-        let length = sequence.len();
-        let mut index;
-
-        // 循环200次，直到选中合适的节点配置为止，或循环200次才跳出循环
-        for _ in 0..200 {
-            // 使用config.yaml中具体哪个节点的配置
-            index = match (1..=(length + 1) as u8).contains(&uri_userid) {
-                true => (uri_userid as usize) - 1, // 选择指定的（数组的下标）
-                false => rand::thread_rng().gen_range(0..length), // 随机选择（数组的下标）
-            };
-
-            let random_https_port = https_ports.choose(&mut rand::thread_rng()).unwrap_or(&443);
-            let random_http_port = http_ports.choose(&mut rand::thread_rng()).unwrap_or(&8080);
+/// 节点转换器，保存一次订阅请求中需要跨地址共享的状态（如随机数生成器、轮询的位置、节点的使用次数）
+pub struct SubConverter<'a> {
+    yamlvalue: &'a YamlValue,
+    uri_params: &'a Params,
+    rng: StdRng,              // 有种子时，相同的输入总是生成相同的订阅
+    candidates: Vec<usize>,   // 符合id、type要求的节点配置（数组的下标）
+    usage: Vec<usize>,        // 每个节点配置已经生成的节点数，用于限制单个节点配置的使用次数
+    node_cursor: usize,       // pairing=roundrobin时，下一个节点配置在candidates中的位置
+    http_cursor: usize,       // 非TLS端口池的轮询位置
+    https_cursor: usize,      // TLS端口池的轮询位置
+    pub skipped: Vec<String>, // 转换目标不支持而跳过的节点配置及原因
+}
 
-            if let Some(yaml_value) = sequence.get_mut(index) {
-                let mut yaml_value_clone = yaml_value.clone();
+impl<'a> SubConverter<'a> {
+    pub fn new(yamlvalue: &'a YamlValue, uri_params: &'a Params) -> Self {
+        let rng = match uri_params.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(), // seed=random，每次都随机
+        };
+        let sequence: &[YamlValue] = yamlvalue.as_sequence().map_or(&[], |seq| seq.as_slice());
+        let length = sequence.len();
 
-                let node_type = yaml_value_clone
+        // 使用config.yaml中哪些节点的配置，id有效就只使用指定的，否则就在所有符合要求的节点中选择
+        let mut skipped = Vec::new();
+        let candidates: Vec<usize> = (0..length)
+            .filter(
+                |&index| match (1..=length).contains(&(uri_params.userid as usize)) {
+                    true => index == (uri_params.userid as usize) - 1,
+                    false => true,
+                },
+            )
+            .filter(|&index| {
+                // token限制使用的节点配置（序号或者名称）
+                let name = sequence[index]
+                    .get("name")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default();
+                (uri_params.allowed_ids.is_empty() || uri_params.allowed_ids.contains(&(index + 1)))
+                    && (uri_params.allowed_names.is_empty()
+                        || uri_params.allowed_names.iter().any(|tag| tag == name))
+            })
+            .filter(|&index| {
+                let node_type = sequence[index]
                     .get("type")
                     .and_then(|v| v.as_str())
-                    .map(|s| s.to_string())
-                    .unwrap_or_else(String::new);
-
-                let host = match node_type.as_str() {
-                    "ss" => get_yaml_value(&yaml_value_clone, &["plugin-opts", "host"])
-                        .and_then(|v| v.as_str())
-                        .unwrap_or_default(),
-                    "vless" | "trojan" => {
-                        get_yaml_value(&yaml_value_clone, &["ws-opts", "headers", "Host"])
+                    .unwrap_or_default();
+                ["ss", "vless", "trojan", "vmess"].contains(&node_type)
+                    && (uri_params.proxy_type == node_type || uri_params.proxy_type == "all")
+            })
+            .filter(|&index| {
+                // 剔除转换目标不支持的节点配置
+                match check_target_node(&uri_params.target, &sequence[index]) {
+                    Ok(()) => true,
+                    Err(reason) => {
+                        tracing::warn!(
+                            "{}{}，已跳过第{}个节点配置",
+                            uri_params.target,
+                            reason,
+                            index + 1
+                        );
+                        let name = sequence[index]
+                            .get("name")
                             .and_then(|v| v.as_str())
-                            .unwrap_or_default()
-                    }
-                    _ => {
-                        continue;
-                    }
-                };
-
-                // 处理 port 、 workers.dev、tls 之间与端口的问题，端口不对就随机生成一个
-                let is_workers_dev = host.ends_with("workers.dev");
-                if node_type == "ss" {
-                    let tls_val = get_yaml_value(&yaml_value_clone, &["plugin-opts", "tls"])
-                        .and_then(|v| v.as_bool())
-                        .unwrap_or(true);
-                    if tls_val && (port == 0 || is_http_ports(port)) {
-                        port = *random_https_port;
-                    } else if !tls_val && (port == 0 || is_https_ports(port)) {
-                        port = *random_http_port;
-                    }
-                } else if ["vless", "trojan"].contains(&node_type.as_str()) {
-                    if is_workers_dev && (port == 0 || is_https_ports(port)) {
-                        port = *random_http_port;
-                    } else if !is_workers_dev && (port == 0 || is_http_ports(port)) {
-                        port = *random_https_port;
+                            .unwrap_or_default();
+                        skipped.push(format!("第{}个节点配置{}：{}", index + 1, name, reason));
+                        false
                     }
                 }
+            })
+            .collect();
 
-                // 根据uri的tls筛选节点配置，是否使用这个节点生成订阅
-                if (is_http_ports(port) && uri_tls_mode == "true")
-                    || (is_https_ports(port) && uri_tls_mode == "false")
-                {
-                    continue;
-                }
+        SubConverter {
+            yamlvalue,
+            uri_params,
+            rng,
+            candidates,
+            usage: vec![0; length],
+            node_cursor: 0,
+            http_cursor: 0,
+            https_cursor: 0,
+            skipped,
+        }
+    }
 
-                if uri_proxy_type == node_type || uri_proxy_type == "all" {
-                    // 节点序号/账号的序号(从1开始)
-                    let padded_index =
-                        format!("{:0width$}", index + 1, width = length.to_string().len());
-                    // 构建完整的节点名称
-                    let remarks: String = format!(
-                        "【{}】{}{}:{}",
-                        padded_index,
-                        csv_remarks,
-                        csv_addr.clone(),
-                        port
-                    );
-                    match uri_target.as_str() {
-                        "v2ray" => {
-                            let (remarks_name, link) = v2ray::build_v2ray_links(
-                                &node_type,
-                                &mut yaml_value_clone,
-                                remarks,
-                                csv_addr,
-                                port,
-                            );
-                            if !remarks_name.is_empty() {
-                                return (remarks_name, link);
-                            }
-                        }
-                        "clash" => {
-                            let clash_node = clash::build_clash_yaml(
-                                &mut yaml_value_clone,
-                                remarks.clone(),
-                                csv_addr,
-                                port,
-                            );
-                            let json_node: String = serde_json::to_string(&clash_node).unwrap();
-                            let json_string = format!("  - {json_node}");
-                            return (remarks, json_string);
-                        }
-                        "singbox" => {
-                            let (remarks_name, json_string) = singbox::build_singbox_config_json(
-                                &node_type,
-                                &mut yaml_value_clone,
-                                remarks,
-                                csv_addr,
-                                port,
-                            );
-                            if !remarks_name.is_empty() {
-                                return (remarks_name, json_string);
-                            }
-                        }
-
-                        _ => {}
-                    }
+    /// 将一个地址跟节点配置配对并转换为节点，返回的元组中，前面是节点的元数据（名称、协议、节点配置的序号），后面是节点配置
+    /// - pairing=random：默认，随机选择一个节点配置
+    /// - pairing=roundrobin：按顺序轮流选择节点配置，让地址均匀分布到各个节点配置中
+    /// - pairing=cartesian：地址跟每个节点配置都配对
+    ///
+    /// expandports=true时，一个地址会展开为端口池中所有端口的节点
+    pub fn subconvert(
+        &mut self,
+        csv_alias: String,
+        csv_addr: String,
+        port: u16,
+    ) -> Vec<(ProxyMeta, String)> {
+        let csv_remarks = match csv_alias.is_empty() {
+            true => String::new(),
+            false => format!("{} | ", csv_alias),
+        };
+        let node_cap = self.uri_params.node_cap;
+        let pairing = self.uri_params.pairing.as_str();
 
-                    break;
-                }
+        // 按配对模式排列节点配置的尝试顺序（前面的节点配置不合适，就尝试下一个）
+        let count = self.candidates.len();
+        let mut order: Vec<usize> = (0..count).collect();
+        match pairing {
+            "cartesian" => {}
+            "roundrobin" => order.rotate_left(self.node_cursor % count.max(1)),
+            _ => order.shuffle(&mut self.rng),
+        }
+
+        let mut nodes = Vec::new();
+        for position in order {
+            let index = self.candidates[position];
+            // 节点配置的使用次数达到上限，就跳过它
+            if node_cap > 0 && self.usage[index] >= node_cap {
+                continue;
+            }
+            let mut built = self.build_nodes(index, &csv_remarks, &csv_addr, port);
+            if built.is_empty() {
+                continue;
+            }
+            if node_cap > 0 {
+                built.truncate(node_cap - self.usage[index]);
+            }
+            self.usage[index] += built.len();
+            nodes.extend(built);
+
+            if pairing != "cartesian" {
+                self.node_cursor = position + 1;
+                break;
             }
         }
+
+        nodes
+    }
+
+    /// 使用config.yaml中第index个节点的配置，构建一个或多个（展开端口时）节点
+    fn build_nodes(
+        &mut self,
+        index: usize,
+        csv_remarks: &str,
+        csv_addr: &str,
+        port: u16,
+    ) -> Vec<(ProxyMeta, String)> {
+        let uri_params = self.uri_params;
+        let sequence = match self.yamlvalue.as_sequence() {
+            Some(sequence) => sequence,
+            None => return Vec::new(),
+        };
+        let yaml_value = &sequence[index];
+        let node_type = yaml_value
+            .get("type")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+
+        // 判断端口类型的闭包
+        let is_https_ports = |port: u16| -> bool { uri_params.https_ports.contains(&port) };
+        let is_http_ports = |port: u16| -> bool { uri_params.http_ports.contains(&port) };
+
+        // 是否开启tls由节点的tls字段（ss为plugin-opts.tls）决定，没有设置才根据workers.dev判断
+        let tls_enabled = TlsOptions::from_yaml(yaml_value, node_type).enabled;
+
+        // 根据portmode分配端口，并根据uri的tls剔除不要的端口
+        let ports: Vec<u16> = self
+            .assign_ports(port, tls_enabled)
+            .into_iter()
+            .filter(|&port| {
+                !((is_http_ports(port) && uri_params.tls_mode == "true")
+                    || (is_https_ports(port) && uri_params.tls_mode == "false"))
+            })
+            .collect();
+
+        // 节点序号/账号的序号(从1开始)
+        let padded_index = format!(
+            "{:0width$}",
+            index + 1,
+            width = sequence.len().to_string().len()
+        );
+        let mut nodes = Vec::new();
+        for port in ports {
+            // 构建完整的节点名称
+            let remarks: String =
+                format!("【{}】{}{}:{}", padded_index, csv_remarks, csv_addr, port);
+            let (remarks_name, node) = build_target_node(
+                &uri_params.target,
+                node_type,
+                &mut yaml_value.clone(),
+                remarks,
+                csv_addr.to_string(),
+                port,
+            );
+            if !remarks_name.is_empty() {
+                let meta = ProxyMeta {
+                    name: remarks_name,
+                    proxy_type: node_type.to_string(),
+                    config_id: index + 1,
+                    ..Default::default()
+                };
+                nodes.push((meta, node));
+            }
+        }
+        nodes
     }
 
-    // 返回的前面是节点名称，后面是节点配置
-    return (String::new(), String::new());
+    /// 根据portmode给节点分配端口，返回空向量表示丢弃这个节点
+    /// - auto：默认，端口跟节点的TLS设置相符就保留，不相符（或没有端口）就随机选一个
+    /// - keep：保留数据中的端口，不相符就丢弃
+    /// - random：总是从端口池中随机选一个
+    /// - fixed：使用fixedport指定的端口，它不在对应的端口池中，就使用端口池的第一个端口
+    /// - roundrobin：按顺序轮流使用端口池中的端口
+    fn assign_ports(&mut self, port: u16, tls_enabled: bool) -> Vec<u16> {
+        let uri_params = self.uri_params;
+        let (pool, other_pool, cursor) = match tls_enabled {
+            true => (
+                &uri_params.https_ports,
+                &uri_params.http_ports,
+                &mut self.https_cursor,
+            ),
+            false => (
+                &uri_params.http_ports,
+                &uri_params.https_ports,
+                &mut self.http_cursor,
+            ),
+        };
+        let mismatch = port == 0 || other_pool.contains(&port);
+
+        if pool.is_empty() {
+            return match mismatch {
+                true => Vec::new(),
+                false => vec![port],
+            };
+        }
+        // 将一个地址展开到端口池的所有端口
+        if uri_params.expand_ports {
+            return pool.clone();
+        }
+
+        let random_port = *pool.choose(&mut self.rng).unwrap_or(&pool[0]);
+        match uri_params.port_mode.as_str() {
+            "keep" => match mismatch {
+                true => Vec::new(),
+                false => vec![port],
+            },
+            "random" => vec![random_port],
+            "fixed" => match pool.contains(&uri_params.fixed_port) {
+                true => vec![uri_params.fixed_port],
+                false => vec![pool[0]],
+            },
+            "roundrobin" => {
+                let rr_port = pool[*cursor % pool.len()];
+                *cursor += 1;
+                vec![rr_port]
+            }
+            _ => match mismatch {
+                true => vec![random_port],
+                false => vec![port],
+            },
+        }
+    }
+}
+
+/// 根据转换目标，构建对应格式的节点，返回的前面是节点名称，后面是节点配置
+fn build_target_node(
+    target: &str,
+    node_type: &str,
+    yaml_value: &mut YamlValue,
+    remarks: String,
+    csv_addr: String,
+    port: u16,
+) -> (String, String) {
+    match target {
+        "v2ray" => v2ray::build_v2ray_links(node_type, yaml_value, remarks, csv_addr, port),
+        "clash" | "clash-provider" => {
+            // 先以JSON保存节点，构建订阅时再统一转换为YAML
+            let clash_node = clash::build_clash_yaml(yaml_value, remarks.clone(), csv_addr, port);
+            let json_node: String = serde_json::to_string(&clash_node).unwrap();
+            (remarks, json_node)
+        }
+        "singbox" => {
+            singbox::build_singbox_config_json(node_type, yaml_value, remarks, csv_addr, port)
+        }
+        "xray" => xray::build_xray_outbound(node_type, yaml_value, remarks, csv_addr, port),
+        "surge" | "surfboard" => {
+            surge::build_surge_proxy(node_type, yaml_value, remarks, csv_addr, port)
+        }
+        "quanx" => quanx::build_quanx_proxy(node_type, yaml_value, remarks, csv_addr, port),
+        "loon" => loon::build_loon_proxy(node_type, yaml_value, remarks, csv_addr, port),
+        _ => (String::new(), String::new()),
+    }
+}
+
+/// 检查转换目标能否表示这个节点配置，不能就返回原因
+fn check_target_node(target: &str, yaml_value: &YamlValue) -> Result<(), String> {
+    let node_type = yaml_value
+        .get("type")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    match target {
+        "xray" => xray::check_xray_node(node_type, yaml_value),
+        "surge" | "surfboard" => surge::check_surge_node(node_type, yaml_value),
+        "quanx" => quanx::check_quanx_node(node_type, yaml_value),
+        "loon" => loon::check_loon_node(node_type, yaml_value),
+        _ => Ok(()),
+    }
+}
+
+/// 将字符串转换为稳定的64位种子（FNV-1a），不随程序版本和运行环境变化
+pub fn stable_seed(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // count个vless节点配置，名称为node1、node2...
+    fn node_configs(count: usize) -> YamlValue {
+        let nodes: Vec<String> = (1..=count)
+            .map(|index| {
+                format!(
+                    "- {{ name: node{}, type: vless, server: a.example.com, port: 443, \
+                     uuid: 0cc14bae-0e4c-4d8d-b0d1-9ab2d4f8e1a2, tls: true, network: ws, \
+                     ws-opts: {{ path: /, headers: {{ Host: a.example.com }} }} }}",
+                    index
+                )
+            })
+            .collect();
+        serde_yaml::from_str(&nodes.join("\n")).unwrap()
+    }
+
+    fn test_params(port_mode: &str) -> Params {
+        Params {
+            target: "v2ray".to_string(),
+            proxy_type: "all".to_string(),
+            tls_mode: "all".to_string(),
+            http_ports: vec![80, 8080],
+            https_ports: vec![443, 2053, 8443],
+            port_mode: port_mode.to_string(),
+            pairing: "random".to_string(),
+            seed: Some(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn auto_and_keep_modes_check_the_port_against_tls() {
+        let configs = node_configs(1);
+        let params = test_params("auto");
+        let mut converter = SubConverter::new(&configs, &params);
+        assert_eq!(converter.assign_ports(2053, true), [2053]);
+        assert_eq!(converter.assign_ports(80, false), [80]);
+        assert_eq!(converter.assign_ports(12345, true), [12345]);
+        for (port, tls) in [(80, true), (0, true), (443, false)] {
+            let pool = match tls {
+                true => &params.https_ports,
+                false => &params.http_ports,
+            };
+            let assigned = converter.assign_ports(port, tls);
+            assert!(assigned.len() == 1 && pool.contains(&assigned[0]));
+        }
+
+        let params = Params {
+            port_mode: "keep".to_string(),
+            ..params
+        };
+        let mut converter = SubConverter::new(&configs, &params);
+        assert_eq!(converter.assign_ports(8443, true), [8443]);
+        assert!(converter.assign_ports(80, true).is_empty());
+        assert!(converter.assign_ports(0, false).is_empty());
+    }
+
+    #[test]
+    fn random_fixed_and_roundrobin_modes_use_the_pool() {
+        let configs = node_configs(1);
+        let params = test_params("random");
+        let mut converter = SubConverter::new(&configs, &params);
+        for _ in 0..10 {
+            let assigned = converter.assign_ports(443, true);
+            assert!(assigned.len() == 1 && params.https_ports.contains(&assigned[0]));
+        }
+
+        let params = Params {
+            port_mode: "fixed".to_string(),
+            fixed_port: 8443,
+            ..params
+        };
+        let mut converter = SubConverter::new(&configs, &params);
+        assert_eq!(converter.assign_ports(443, true), [8443]);
+        // 固定端口不在非TLS的端口池中，使用端口池的第一个端口
+        assert_eq!(converter.assign_ports(8080, false), [80]);
+
+        let params = Params {
+            port_mode: "roundrobin".to_string(),
+            ..params
+        };
+        let mut converter = SubConverter::new(&configs, &params);
+        let ports: Vec<u16> = (0..4)
+            .flat_map(|_| converter.assign_ports(0, true))
+            .collect();
+        assert_eq!(ports, [443, 2053, 8443, 443]);
+        assert_eq!(converter.assign_ports(0, false), [80]);
+    }
+
+    #[test]
+    fn expand_ports_and_empty_pools() {
+        let configs = node_configs(1);
+        let params = Params {
+            expand_ports: true,
+            ..test_params("auto")
+        };
+        let mut converter = SubConverter::new(&configs, &params);
+        assert_eq!(converter.assign_ports(80, true), [443, 2053, 8443]);
+
+        let params = Params {
+            https_ports: Vec::new(),
+            ..test_params("auto")
+        };
+        let mut converter = SubConverter::new(&configs, &params);
+        assert_eq!(converter.assign_ports(12345, true), [12345]);
+        assert!(converter.assign_ports(80, true).is_empty());
+    }
+
+    // 转换多个地址，返回每个节点使用的节点配置序号和节点内容
+    fn convert_addresses(
+        params: &Params,
+        configs: &YamlValue,
+        count: usize,
+    ) -> Vec<(usize, String)> {
+        let mut converter = SubConverter::new(configs, params);
+        (1..=count)
+            .flat_map(|index| {
+                converter.subconvert(String::new(), format!("104.16.0.{}", index), 443)
+            })
+            .map(|(meta, node)| (meta.config_id, node))
+            .collect()
+    }
+
+    #[test]
+    fn stable_seed_is_fnv1a() {
+        assert_eq!(stable_seed(""), 0xcbf29ce484222325);
+        assert_eq!(stable_seed("a"), 0xaf63dc4c8601ec8c);
+        assert_ne!(
+            stable_seed("n=10&target=clash"),
+            stable_seed("n=10&target=v2ray")
+        );
+    }
+
+    #[test]
+    fn same_seed_gives_same_nodes() {
+        let configs = node_configs(5);
+        let params = test_params("random");
+        let first = convert_addresses(&params, &configs, 20);
+        assert_eq!(first.len(), 20);
+        assert_eq!(first, convert_addresses(&params, &configs, 20));
+
+        let other = Params {
+            seed: Some(2),
+            ..params.clone()
+        };
+        assert_ne!(first, convert_addresses(&other, &configs, 20));
+    }
+
+    #[test]
+    fn roundrobin_pairing_spreads_addresses_evenly() {
+        let configs = node_configs(3);
+        let params = Params {
+            pairing: "roundrobin".to_string(),
+            ..test_params("auto")
+        };
+        let ids: Vec<usize> = convert_addresses(&params, &configs, 6)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ids, [1, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn cartesian_pairing_uses_every_config() {
+        let configs = node_configs(3);
+        let params = Params {
+            pairing: "cartesian".to_string(),
+            ..test_params("auto")
+        };
+        let ids: Vec<usize> = convert_addresses(&params, &configs, 2)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ids, [1, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn node_cap_limits_nodes_per_config() {
+        let configs = node_configs(3);
+        let params = Params {
+            node_cap: 2,
+            ..test_params("auto")
+        };
+        let nodes = convert_addresses(&params, &configs, 10);
+        assert_eq!(nodes.len(), 6);
+        for id in 1..=3 {
+            assert_eq!(nodes.iter().filter(|(x, _)| *x == id).count(), 2);
+        }
+
+        // 展开端口时，一个地址的节点数也受上限的限制
+        let params = Params {
+            node_cap: 2,
+            expand_ports: true,
+            pairing: "cartesian".to_string(),
+            ..test_params("auto")
+        };
+        let nodes = convert_addresses(&params, &configs, 3);
+        assert_eq!(nodes.len(), 6);
+    }
+
+    #[test]
+    fn allowed_ids_select_configs_past_255() {
+        let configs = node_configs(300);
+        let params = Params {
+            target: "v2ray".to_string(),
+            proxy_type: "all".to_string(),
+            allowed_ids: vec![257],
+            ..Default::default()
+        };
+        let converter = SubConverter::new(&configs, &params);
+        assert_eq!(converter.candidates, [256]);
+
+        let params = Params {
+            allowed_ids: Vec::new(),
+            allowed_names: vec!["node2".to_string()],
+            ..params
+        };
+        let converter = SubConverter::new(&configs, &params);
+        assert_eq!(converter.candidates, [1]);
+    }
 }
@@ -0,0 +1,93 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use std::fmt;
+
+/// 处理订阅请求时的错误，每种错误对应一个HTTP状态码，响应内容为简短的说明（纯文本）
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),   // 400：订阅链接的参数无效
+    Unauthorized(String), // 401：没有token或者token无效
+    Forbidden(String),    // 403：token的限制、数据源不允许使用
+    NotFound(String),     // 404：没有数据、页码超出范围、没有符合条件的节点配置
+    BadGateway(String),   // 502：获取网络数据失败
+    Internal(String),     // 500：config.yaml、模板等服务器端的文件有问题
+}
+
+impl AppError {
+    fn message(&self) -> &str {
+        match self {
+            AppError::BadRequest(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::BadGateway(message)
+            | AppError::Internal(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        response.content_type("text/plain; charset=utf-8");
+        if let AppError::Unauthorized(_) = self {
+            response.insert_header(("WWW-Authenticate", "Bearer"));
+        }
+        response.body(self.message().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::MessageBody;
+
+    #[test]
+    fn errors_map_to_statuses() {
+        let cases = [
+            (AppError::BadRequest("a".to_string()), 400),
+            (AppError::Unauthorized("b".to_string()), 401),
+            (AppError::Forbidden("c".to_string()), 403),
+            (AppError::NotFound("d".to_string()), 404),
+            (AppError::BadGateway("e".to_string()), 502),
+            (AppError::Internal("f".to_string()), 500),
+        ];
+        for (error, status) in cases {
+            assert_eq!(error.status_code().as_u16(), status);
+        }
+    }
+
+    #[test]
+    fn error_response_is_plain_text_message() {
+        let response = AppError::Unauthorized("需要token".to_string()).error_response();
+        assert_eq!(
+            response.headers().get("WWW-Authenticate").unwrap(),
+            "Bearer"
+        );
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        let body = response.into_body().try_into_bytes().unwrap();
+        assert_eq!(body, "需要token".as_bytes());
+
+        let response = AppError::NotFound("x".to_string()).error_response();
+        assert!(!response.headers().contains_key("WWW-Authenticate"));
+    }
+}
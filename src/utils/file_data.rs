@@ -1,3 +1,4 @@
+use crate::utils::{ access_log, error::AppError, metrics };
 use csv::ReaderBuilder;
 use std::{
     collections::HashMap,
@@ -15,6 +16,10 @@ pub struct MyData {
     pub addr: String,
     pub port: Option<u16>,
     pub alias: Option<String>,
+    pub colo: Option<String>, // 以下字段用于代理组的筛选和分组
+    pub loc: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
 }
 
 #[derive(Default)]
@@ -88,6 +93,7 @@ fn process_csv(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<d
     let city_index = find_index("city");
 
     let mut result: Vec<FileData> = Vec::new();
+    let mut skipped = 0; // 没有地址的行数
 
     for record in rdr.records() {
         let record = record?;
@@ -96,6 +102,7 @@ fn process_csv(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<d
         let addr_column = addr_index.and_then(|index| record.get(index)).unwrap_or("");
 
         if addr_column.is_empty() {
+            skipped += 1;
             continue;
         }
 
@@ -108,7 +115,7 @@ fn process_csv(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<d
         let get_column_string = |index: Option<usize>| {
             index
                 .and_then(|idx| record.get(idx).and_then(|val| val.parse().ok())) // 隐式转换
-                .unwrap_or_else(|| "".to_string()) // 默认为空字符串
+                .unwrap_or_default() // 默认为空字符串
         };
 
         // 使用闭包提取列数据，没有找到对应的列时，返回空字符串
@@ -127,6 +134,7 @@ fn process_csv(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<d
         };
         result.push(data);
     }
+    metrics::record_rows("local", result.len(), skipped);
 
     Ok(result)
 }
@@ -134,20 +142,26 @@ fn process_csv(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<d
 fn process_txt(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<dyn Error>> {
     // 排除不需要的txt文件，注意：如果传入完整的路径，该功能不起作用
     if filename.starts_with("ips-v") || filename.starts_with("ipv") {
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Skipping this file")));
+        return Err(Box::new(std::io::Error::other("Skipping this file")));
     }
     let file = File::open(filename)?;
     let reader = BufReader::new(file);
 
     let mut seen_lines: Vec<String> = Vec::new();
     let mut result: Vec<FileData> = Vec::new();
+    let mut skipped = 0; // CIDR、重复和无法解析的行数
+    let mut unsupported = 0; // 无法解析的行数
 
     for line in reader.lines() {
         let line = line?;
         let trimmed_line = line.trim().to_string();
 
+        if trimmed_line.is_empty() {
+            continue;
+        }
         let contains_bool = trimmed_line.contains("/") || seen_lines.contains(&trimmed_line);
-        if trimmed_line.is_empty() || contains_bool {
+        if contains_bool {
+            skipped += 1;
             continue;
         }
 
@@ -236,11 +250,22 @@ fn process_txt(filename: &str, default_port: u16) -> Result<Vec<FileData>, Box<d
                 };
                 seen_lines.push(final_line);
                 result.push(data);
+            } else {
+                skipped += 1;
             }
         } else {
-            println!("不支持提取 `{}` 的地址和端口！", trimmed_line);
+            skipped += 1;
+            unsupported += 1;
         }
     }
+    if unsupported > 0 {
+        tracing::warn!(
+            "数据文件{}中有{}行不支持提取地址和端口，已忽略",
+            access_log::redact_source(filename),
+            unsupported
+        );
+    }
+    metrics::record_rows("local", result.len(), skipped);
 
     Ok(result)
 }
@@ -261,12 +286,12 @@ pub fn process_files_data(
     default_port: u16,
     count: usize,
     target_path: &str
-) -> Vec<MyData> {
+) -> Result<Vec<MyData>, AppError> {
     let mut results: Vec<MyData> = Vec::new(); // 存储结果
     let mut seen_addr: Vec<String> = Vec::new(); // 数据去重
 
     let path = Path::new(target_path);
-    if path.is_file() && path.extension().map_or(false, |ext| (ext == "txt" || ext == "csv")) {
+    if path.is_file() && path.extension().is_some_and(|ext| ext == "txt" || ext == "csv" ) {
         // 如果路径是 TXT 或 CSV 文件，直接处理该文件
         match process_file(target_path, default_port) {
             Ok(data) => {
@@ -296,29 +321,48 @@ pub fn process_files_data(
                         addr: addr.clone(),
                         port: Some(port),
                         alias: alias_prefix,
+                        colo: item.colo.clone(),
+                        loc: item.loc.clone(),
+                        region: item.region.clone(),
+                        city: item.city.clone(),
                     };
 
                     // 如果结果数量小于指定的数量，则添加数据，否则就返回，避免无意义的IO操作(读取数据)
                     if results.len() < count {
                         results.push(data.clone());
                     } else {
-                        return results; // 直接返回结果，因为是处理单个文件
+                        return Ok(results); // 直接返回结果，因为是处理单个文件
                     }
                 }
             }
-            Err(e) => eprintln!("处理文件 `{}` 出错: {}", target_path, e),
+            Err(e) => {
+                tracing::warn!("处理文件{}出错：{}", access_log::redact_source(target_path), e);
+                return Err(AppError::Internal("读取数据文件出错".to_string()));
+            }
         }
     } else if path.is_dir() {
         // 如果路径是文件夹，执行原来的逻辑
-        let msg = format!("文件夹 {} 不存在！", target_path);
-        let entries = fs::read_dir(target_path).expect(&msg);
-        'outer: for entry in entries {
-            let entry = entry.unwrap();
-            let path = entry.path();
+        let entries = match fs::read_dir(target_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::warn!("读取文件夹{}出错：{}", access_log::redact_source(target_path), e);
+                return Err(AppError::Internal("读取数据文件夹出错".to_string()));
+            }
+        };
+        // 按文件名排序，保证每次读取数据的顺序一致（read_dir的顺序跟文件系统有关）
+        let mut paths: Vec<_> = entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect();
+        paths.sort();
+        // 文件夹中指向文件夹以外的符号链接不读取
+        let canonical_dir = fs::canonicalize(target_path).unwrap_or_default();
+        'outer: for path in paths {
+            if !fs::canonicalize(&path).is_ok_and(|real_path| real_path.starts_with(&canonical_dir)) {
+                tracing::warn!("跳过指向数据文件夹以外的文件{}", access_log::redact_source(&path.to_string_lossy()));
+                continue;
+            }
 
             let is_txt_or_csv = path
                 .extension()
-                .map_or(false, |ext| (ext == "txt" || ext == "csv"));
+                .is_some_and(|ext| ext == "txt" || ext == "csv" );
 
             // 只处理txt和csv文件，process_file函数中，含有排除的txt文件
             if path.is_file() && is_txt_or_csv {
@@ -352,6 +396,10 @@ pub fn process_files_data(
                                 addr: addr.clone(),
                                 port: Some(port),
                                 alias: alias_prefix,
+                                colo: item.colo.clone(),
+                                loc: item.loc.clone(),
+                                region: item.region.clone(),
+                                city: item.city.clone(),
                             };
 
                             // 获取足够的数据，就停止for循环
@@ -362,13 +410,14 @@ pub fn process_files_data(
                             }
                         }
                     }
-                    Err(e) => eprintln!("处理文件 `{}` 出错: {}", filename, e),
+                    Err(e) => tracing::warn!("处理文件{}出错：{}", access_log::redact_source(&filename), e),
                 }
             }
         }
     } else {
-        eprintln!("路径 {} 不是有效的文件或文件夹。", target_path);
+        tracing::warn!("数据源{}不是有效的文件或文件夹", access_log::redact_source(target_path));
+        return Err(AppError::NotFound("数据源不是txt、csv文件或者文件夹".to_string()));
     }
 
-    results
+    Ok(results)
 }
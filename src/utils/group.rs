@@ -0,0 +1,202 @@
+use regex::Regex;
+
+/// 节点的元数据，用于在代理组中按条件筛选节点
+#[derive(Default, Clone)]
+pub struct ProxyMeta {
+    pub name: String,
+    pub proxy_type: String, // vless、trojan、ss、vmess
+    pub config_id: usize,   // 使用config.yaml中第几个节点配置（从1开始）
+    pub colo: String,       // 数据中心(3位字母)
+    pub loc: String,        // 国家代码/地区代码(2位字母)
+    pub region: String,
+    pub city: String,
+}
+
+/// 展开代理组成员中的筛选标记，其它成员（如其它代理组、DIRECT）原样保留
+/// - `{all}`：所有节点
+/// - `{type:vless}`：指定协议的节点，多个协议用逗号分隔，如`{type:vless,trojan}`
+/// - `{regex:^HK}`：节点名称匹配正则表达式的节点
+/// - `{colo:LAX}`、`{loc:US}`（或`{country:US}`）、`{region:...}`、`{city:...}`：数据中心、国家等字段匹配的节点
+/// - `{node:1}`（或`{id:1}`）：使用config.yaml中第几个节点配置生成的节点
+///
+/// 除了regex，参数都可以用逗号分隔多个值，不区分大小写
+pub fn expand_group_members(members: &[String], proxies: &[ProxyMeta]) -> Vec<String> {
+    let mut expanded: Vec<String> = Vec::new();
+    for member in members {
+        let names: Vec<String> = match parse_marker(member) {
+            Some((kind, arg)) => match filter_proxies(kind, arg, proxies) {
+                Ok(names) => names,
+                Err(reason) => {
+                    tracing::warn!("代理组的筛选标记{}无效：{}，已忽略", member, reason);
+                    Vec::new()
+                }
+            },
+            None => vec![member.clone()],
+        };
+        for name in names {
+            if !expanded.contains(&name) {
+                expanded.push(name);
+            }
+        }
+    }
+    expanded
+}
+
+/// 判断是否为筛选标记，是就返回(类型, 参数)，如"{type:vless}" -> ("type", "vless")
+fn parse_marker(member: &str) -> Option<(&str, &str)> {
+    let inner = member.trim().strip_prefix('{')?.strip_suffix('}')?;
+    match inner.split_once(':') {
+        Some((kind, arg)) => Some((kind.trim(), arg.trim())),
+        None => Some((inner.trim(), "")),
+    }
+}
+
+fn filter_proxies(kind: &str, arg: &str, proxies: &[ProxyMeta]) -> Result<Vec<String>, String> {
+    let values: Vec<String> = arg.split(',').map(|s| s.trim().to_lowercase()).collect();
+    let field_matches = |field: &str| values.contains(&field.to_lowercase());
+    let matched: Vec<&ProxyMeta> = match kind.to_lowercase().as_str() {
+        "all" => proxies.iter().collect(),
+        "type" | "proxy" | "protocol" => proxies
+            .iter()
+            .filter(|proxy| field_matches(&proxy.proxy_type))
+            .collect(),
+        "colo" => proxies
+            .iter()
+            .filter(|proxy| field_matches(&proxy.colo))
+            .collect(),
+        "loc" | "country" => proxies
+            .iter()
+            .filter(|proxy| field_matches(&proxy.loc))
+            .collect(),
+        "region" => proxies
+            .iter()
+            .filter(|proxy| field_matches(&proxy.region))
+            .collect(),
+        "city" => proxies
+            .iter()
+            .filter(|proxy| field_matches(&proxy.city))
+            .collect(),
+        "node" | "id" => proxies
+            .iter()
+            .filter(|proxy| field_matches(&proxy.config_id.to_string()))
+            .collect(),
+        "regex" => {
+            let regex = Regex::new(arg).map_err(|e| e.to_string())?;
+            proxies
+                .iter()
+                .filter(|proxy| regex.is_match(&proxy.name))
+                .collect()
+        }
+        _ => return Err(format!("不支持的标记类型{}", kind)),
+    };
+    Ok(matched.iter().map(|proxy| proxy.name.clone()).collect())
+}
+
+/// groupby生成的顶层选择组的名称
+pub const GROUPBY_SELECTOR_NAME: &str = "🗺️ 自动分组";
+
+/// 按字段（colo、loc、region、city、node）给节点分组，返回(分组名称, 节点名称)，
+/// 分组按第一次出现的顺序排列，字段值为空的节点不参与分组；field为空就不分组
+pub fn group_proxies_by(field: &str, proxies: &[ProxyMeta]) -> Vec<(String, Vec<String>)> {
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    if field.is_empty() {
+        return groups;
+    }
+    for proxy in proxies {
+        let value = match field {
+            "colo" => proxy.colo.clone(),
+            "loc" => proxy.loc.clone(),
+            "region" => proxy.region.clone(),
+            "city" => proxy.city.clone(),
+            "node" => format!("节点配置{}", proxy.config_id),
+            _ => String::new(),
+        };
+        if value.is_empty() {
+            continue;
+        }
+        let group_name = format!("📍 {}", value);
+        match groups.iter_mut().find(|(name, _)| *name == group_name) {
+            Some((_, members)) => members.push(proxy.name.clone()),
+            None => groups.push((group_name, vec![proxy.name.clone()])),
+        }
+    }
+    if groups.is_empty() {
+        tracing::warn!("数据中没有{}字段的值，没有生成分组", field);
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies() -> Vec<ProxyMeta> {
+        [
+            ("HK-vless-1", "vless", 1, "HKG", "HK"),
+            ("US-trojan-2", "trojan", 2, "LAX", "US"),
+            ("US-ss-3", "ss", 3, "SJC", "US"),
+        ]
+        .iter()
+        .map(|(name, proxy_type, config_id, colo, loc)| ProxyMeta {
+            name: name.to_string(),
+            proxy_type: proxy_type.to_string(),
+            config_id: *config_id,
+            colo: colo.to_string(),
+            loc: loc.to_string(),
+            ..Default::default()
+        })
+        .collect()
+    }
+
+    fn expand(members: &[&str]) -> Vec<String> {
+        let members: Vec<String> = members.iter().map(|x| x.to_string()).collect();
+        expand_group_members(&members, &proxies())
+    }
+
+    #[test]
+    fn markers_filter_proxies() {
+        assert_eq!(expand(&["{all}"]), ["HK-vless-1", "US-trojan-2", "US-ss-3"]);
+        assert_eq!(expand(&["{type:VLESS, ss}"]), ["HK-vless-1", "US-ss-3"]);
+        assert_eq!(expand(&["{colo:lax}"]), ["US-trojan-2"]);
+        assert_eq!(expand(&["{country:us}"]), ["US-trojan-2", "US-ss-3"]);
+        assert_eq!(expand(&["{node:1,3}"]), ["HK-vless-1", "US-ss-3"]);
+        assert_eq!(expand(&["{regex:^US-.*-2$}"]), ["US-trojan-2"]);
+    }
+
+    #[test]
+    fn other_members_are_kept_and_duplicates_removed() {
+        assert_eq!(
+            expand(&["auto", "{loc:HK}", "{type:vless}", "DIRECT"]),
+            ["auto", "HK-vless-1", "DIRECT"]
+        );
+    }
+
+    #[test]
+    fn invalid_markers_match_nothing() {
+        assert!(expand(&["{regex:(}"]).is_empty());
+        assert!(expand(&["{asn:13335}"]).is_empty());
+        assert!(expand(&["{loc:JP}"]).is_empty());
+    }
+
+    #[test]
+    fn proxies_are_grouped_in_first_seen_order() {
+        let mut proxies = proxies();
+        proxies[0].loc = String::new();
+        assert_eq!(
+            group_proxies_by("loc", &proxies),
+            [(
+                "📍 US".to_string(),
+                vec!["US-trojan-2".to_string(), "US-ss-3".to_string()]
+            )]
+        );
+        let by_node = group_proxies_by("node", &proxies);
+        let names: Vec<&str> = by_node.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["📍 节点配置1", "📍 节点配置2", "📍 节点配置3"]);
+    }
+
+    #[test]
+    fn groupby_is_optional() {
+        assert!(group_proxies_by("", &proxies()).is_empty());
+        assert!(group_proxies_by("city", &proxies()).is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+use super::{config::get_yaml_value, tls::TlsOptions};
+use serde_yaml::Value as YamlValue;
+
+/// 检查节点能否转换为Loon的代理，不能转换就返回原因
+pub fn check_loon_node(node_type: &str, yaml_value: &YamlValue) -> Result<(), String> {
+    match node_type {
+        "vless" | "vmess" => Ok(()),
+        "trojan" => match TlsOptions::from_yaml(yaml_value, node_type).enabled {
+            true => Ok(()),
+            false => Err("不支持非TLS的trojan".to_string()),
+        },
+        "ss" => {
+            let plugin = get_yaml_value(yaml_value, &["plugin"])
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+            match plugin {
+                "" | "obfs" => Ok(()),
+                _ => Err(format!("不支持ss的{}插件", plugin)),
+            }
+        }
+        _ => Err(format!("不支持{}协议", node_type)),
+    }
+}
+
+/// 构建Loon的[Proxy]代理行，返回的前面是节点名称，后面是代理行
+pub fn build_loon_proxy(
+    proxy_type: &str,
+    yaml_value: &mut YamlValue,
+    remarks: String,
+    server_address: String,
+    server_port: u16,
+) -> (String, String) {
+    if check_loon_node(proxy_type, yaml_value).is_err() {
+        return (String::new(), String::new());
+    }
+    // 节点名称中的逗号、等号会破坏配置文件的格式
+    let remarks = remarks.replace([',', '='], " ");
+    // Loon的IPv6地址不需要方括号
+    let server = server_address.trim_start_matches('[').trim_end_matches(']');
+
+    let get_str = |keys: &[&str]| -> String {
+        get_yaml_value(yaml_value, keys)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    // 协议名称和认证信息
+    let mut fields: Vec<String> = match proxy_type {
+        "vless" => vec!["VLESS".to_string(), format!("\"{}\"", get_str(&["uuid"]))],
+        "vmess" => {
+            let cipher = match get_str(&["cipher"]).as_str() {
+                "" => "auto".to_string(),
+                cipher => cipher.to_string(),
+            };
+            vec![
+                "vmess".to_string(),
+                cipher,
+                format!("\"{}\"", get_str(&["uuid"])),
+            ]
+        }
+        "trojan" => vec![
+            "trojan".to_string(),
+            format!("\"{}\"", get_str(&["password"])),
+        ],
+        _ => vec![
+            "Shadowsocks".to_string(),
+            get_str(&["cipher"]),
+            format!("\"{}\"", get_str(&["password"])),
+        ],
+    };
+    fields.insert(1, format!("{},{}", server, server_port));
+
+    if proxy_type == "ss" {
+        let obfs_mode = get_str(&["plugin-opts", "mode"]);
+        if !obfs_mode.is_empty() {
+            fields.push(format!("obfs-name={}", obfs_mode));
+            fields.push(format!("obfs-host={}", get_str(&["plugin-opts", "host"])));
+            fields.push("obfs-uri=/".to_string());
+        }
+    } else {
+        if get_str(&["network"]) == "ws" {
+            fields.push("transport=ws".to_string());
+            fields.push(format!("path={}", get_str(&["ws-opts", "path"])));
+            fields.push(format!("host={}", get_str(&["ws-opts", "headers", "Host"])));
+        }
+        let tls = TlsOptions::from_yaml(yaml_value, proxy_type);
+        // Loon的trojan总是使用TLS，不需要over-tls参数
+        if proxy_type != "trojan" {
+            fields.push(format!("over-tls={}", tls.enabled));
+        }
+        if tls.enabled {
+            if !tls.server_name.is_empty() {
+                fields.push(format!("sni={}", tls.server_name));
+            }
+            fields.push(format!("skip-cert-verify={}", tls.skip_cert_verify));
+        }
+        if proxy_type == "vmess" {
+            let alter_id = get_yaml_value(yaml_value, &["alterId"])
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0);
+            fields.push(format!("alterId={}", alter_id));
+        }
+    }
+
+    let loon_proxy = format!("{} = {}", remarks, fields.join(","));
+
+    (remarks, loon_proxy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(proxy_type: &str, yaml: &str) -> (String, String) {
+        let mut yaml_value: YamlValue = serde_yaml::from_str(yaml).unwrap();
+        build_loon_proxy(
+            proxy_type,
+            &mut yaml_value,
+            "US=LAX".to_string(),
+            "[2606:4700::1]".to_string(),
+            443,
+        )
+    }
+
+    #[test]
+    fn unsupported_nodes_are_skipped() {
+        let trojan: YamlValue = serde_yaml::from_str("{ password: pw, tls: false }").unwrap();
+        assert!(check_loon_node("trojan", &trojan).is_err());
+        assert_eq!(
+            build("ss", "plugin: v2ray-plugin"),
+            (String::new(), String::new())
+        );
+    }
+
+    #[test]
+    fn vless_ws_tls_proxy_line() {
+        let (name, line) = build(
+            "vless",
+            "{ uuid: abc, network: ws, sni: a.example.com, \
+             ws-opts: { path: /ws, headers: { Host: a.example.com } } }",
+        );
+        assert_eq!(name, "US LAX");
+        assert_eq!(
+            line,
+            "US LAX = VLESS,2606:4700::1,443,\"abc\",transport=ws,path=/ws,host=a.example.com,\
+             over-tls=true,sni=a.example.com,skip-cert-verify=true"
+        );
+    }
+
+    #[test]
+    fn vmess_and_ss_proxy_lines() {
+        let (_, vmess) = build("vmess", "{ uuid: abc, tls: false, alterId: 0 }");
+        assert_eq!(
+            vmess,
+            "US LAX = vmess,2606:4700::1,443,auto,\"abc\",over-tls=false,alterId=0"
+        );
+        let (_, ss) = build(
+            "ss",
+            "{ cipher: aes-128-gcm, password: pw, plugin: obfs, \
+             plugin-opts: { mode: http, host: a.example.com } }",
+        );
+        assert_eq!(
+            ss,
+            "US LAX = Shadowsocks,2606:4700::1,443,aes-128-gcm,\"pw\",obfs-name=http,\
+             obfs-host=a.example.com,obfs-uri=/"
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use std::time::Duration;
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+    // 请求数：路由（如/sub/{token}，不含实际的token）、转换目标、HTTP状态码
+    static ref HTTP_REQUESTS: IntCounterVec = register_counter(
+        "cfwks_http_requests_total",
+        "处理的HTTP请求数",
+        &["route", "target", "status"],
+    );
+    // 创建订阅内容（分拣数据、生成节点、套用模板）的耗时
+    static ref BUILD_DURATION: HistogramVec = register_histogram(
+        "cfwks_subscription_build_seconds",
+        "创建订阅内容的耗时（秒）",
+        &["target"],
+    );
+    static ref NODES_EMITTED: IntCounterVec = register_counter(
+        "cfwks_nodes_emitted_total",
+        "订阅中返回的节点数",
+        &["target"],
+    );
+    // 本地（local）和网络（remote）数据源解析出的地址数，以及无法解析、被忽略的行数；
+    // 不使用文件路径或链接作为标签，避免暴露服务器的目录结构和上游链接，标签值也不会无限增加
+    static ref ROWS_PARSED: IntCounterVec = register_counter(
+        "cfwks_source_rows_parsed_total",
+        "数据源中解析出地址的行数",
+        &["source"],
+    );
+    static ref ROWS_SKIPPED: IntCounterVec = register_counter(
+        "cfwks_source_rows_skipped_total",
+        "数据源中无法解析或者被忽略的行数",
+        &["source"],
+    );
+    // kind为data（网络数据源）或template（远程模板）
+    static ref REMOTE_FETCH_ERRORS: IntCounterVec = register_counter(
+        "cfwks_remote_fetch_errors_total",
+        "获取网络数据或远程模板失败的次数",
+        &["kind"],
+    );
+    // result为hit（缓存没有过期）、miss（重新获取）或stale（获取失败，使用过期的缓存）
+    static ref TEMPLATE_CACHE: IntCounterVec = register_counter(
+        "cfwks_template_cache_total",
+        "远程模板缓存的使用情况",
+        &["result"],
+    );
+}
+
+// 指标的名称和标签是固定的，创建或注册失败属于程序错误
+fn register_counter(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).expect("无效的指标");
+    REGISTRY
+        .register(Box::new(counter.clone()))
+        .expect("指标重复注册");
+    counter
+}
+
+fn register_histogram(name: &str, help: &str, labels: &[&str]) -> HistogramVec {
+    let buckets = vec![
+        0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+    ];
+    let histogram = HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets), labels)
+        .expect("无效的指标");
+    REGISTRY
+        .register(Box::new(histogram.clone()))
+        .expect("指标重复注册");
+    histogram
+}
+
+/// 记录一个HTTP请求，target为access_log::target_label处理后的转换目标（none、invalid或者支持的转换目标）
+pub fn record_request(route: &str, target: &str, status: u16) {
+    HTTP_REQUESTS
+        .with_label_values(&[route, target, &status.to_string()])
+        .inc();
+}
+
+/// 记录一次创建订阅的耗时和返回的节点数
+pub fn record_build(target: &str, elapsed: Duration, node_count: usize) {
+    BUILD_DURATION
+        .with_label_values(&[target])
+        .observe(elapsed.as_secs_f64());
+    NODES_EMITTED
+        .with_label_values(&[target])
+        .inc_by(node_count as u64);
+}
+
+/// 记录一个数据文件（或链接）解析出的行数和被忽略的行数，source为local或remote
+pub fn record_rows(source: &str, parsed: usize, skipped: usize) {
+    ROWS_PARSED
+        .with_label_values(&[source])
+        .inc_by(parsed as u64);
+    ROWS_SKIPPED
+        .with_label_values(&[source])
+        .inc_by(skipped as u64);
+}
+
+/// 记录一次获取网络数据（data）或远程模板（template）失败
+pub fn record_fetch_error(kind: &str) {
+    REMOTE_FETCH_ERRORS.with_label_values(&[kind]).inc();
+}
+
+/// 记录一次远程模板缓存的使用情况（hit、miss、stale）
+pub fn record_template_cache(result: &str) {
+    TEMPLATE_CACHE.with_label_values(&[result]).inc();
+}
+
+/// Prometheus文本格式的所有指标
+pub fn gather() -> String {
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        tracing::error!("导出指标失败：{}", e);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
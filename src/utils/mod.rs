@@ -1,9 +1,24 @@
+pub mod access_log;
+pub mod auth;
 pub mod clash;
 pub mod config;
 pub mod convert;
+pub mod error;
 pub mod file_data;
+pub mod group;
+pub mod https;
+pub mod loon;
+pub mod metrics;
 pub mod net_data;
+pub mod overrides;
+pub mod profile;
 pub mod qrcode;
+pub mod quanx;
+pub mod server_config;
 pub mod singbox;
+pub mod surge;
 pub mod v2ray;
+pub mod xray;
 pub mod build;
+pub mod template;
+pub mod tls;
@@ -1,21 +1,50 @@
-use crate::utils::file_data::{ self, FileData, MyData };
+use crate::utils::{
+    access_log,
+    error::AppError,
+    file_data::{ self, FileData, MyData },
+    metrics,
+    server_config::check_allowed_url,
+};
 use reqwest;
 use csv::ReaderBuilder;
 use crossbeam_channel::unbounded;
 use std::{ error::Error, thread };
 
+// 获取网络数据的客户端，重定向后的地址也必须在允许的主机列表中
+fn build_client(allowed_hosts: &[String]) -> reqwest::Result<reqwest::Client> {
+    let allowed_hosts = allowed_hosts.to_vec();
+    let redirect_policy = reqwest::redirect::Policy::custom(move |attempt| {
+        match
+            attempt.previous().len() < 5 &&
+            check_allowed_url(attempt.url().as_str(), &allowed_hosts, "").is_ok()
+        {
+            true => attempt.follow(),
+            false => attempt.stop(),
+        }
+    });
+    reqwest::Client::builder().redirect(redirect_policy).build()
+}
+
 // 同步函数中使用异步，使用 std::thread::spawn 在另一个线程中运行异步代码
 fn read_csv_from_url(
     url: &str,
-    default_port: u16
+    default_port: u16,
+    allowed_hosts: &[String]
 ) -> Result<Vec<FileData>, Box<dyn Error + Send + Sync>> {
     let (sender, receiver) = unbounded();
     let url_copy = url.to_string();
+    let allowed_hosts = allowed_hosts.to_vec();
 
     thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
+        let rt = match tokio::runtime::Runtime::new() {
+            Ok(rt) => rt,
+            Err(e) => {
+                let _ = sender.send(Err(e.into()));
+                return;
+            }
+        };
         let result = rt.block_on(async {
-            let response = reqwest::get(&url_copy).await?;
+            let response = build_client(&allowed_hosts)?.get(&url_copy).send().await?;
             if !response.status().is_success() {
                 return Err(format!("Failed to fetch CSV from URL: {}", response.status()).into());
             }
@@ -46,6 +75,7 @@ fn read_csv_from_url(
 
             // 1. 将 CSV 记录转换为结构体实例，并收集到向量
             let mut records: Vec<FileData> = Vec::new();
+            let mut skipped = 0; // 没有地址的行数
 
             for record in rdr.records() {
                 let record = record?;
@@ -54,6 +84,7 @@ fn read_csv_from_url(
                 let addr_column = addr_index.and_then(|index| record.get(index)).unwrap_or("");
 
                 if addr_column.is_empty() {
+                    skipped += 1;
                     continue;
                 }
 
@@ -66,7 +97,7 @@ fn read_csv_from_url(
                 let get_column_string = |index: Option<usize>| {
                     index
                         .and_then(|idx| record.get(idx).and_then(|val| val.parse().ok())) // 隐式转换
-                        .unwrap_or_else(|| "".to_string()) // 默认为空字符串
+                        .unwrap_or_default() // 默认为空字符串
                 };
 
                 // 使用闭包提取列数据，没有找到对应的列时，返回空字符串
@@ -85,37 +116,52 @@ fn read_csv_from_url(
                 };
                 records.push(data);
             }
+            metrics::record_rows("remote", records.len(), skipped);
             Ok(records) // 2. 返回向量，这个类似return
         });
-        sender.send(result).unwrap(); // 3.  这里将这个 result 发送到通道中
+        let _ = sender.send(result); // 3.  这里将这个 result 发送到通道中
     });
 
-    // 4. 接收通道中的结果
-    receiver.recv().unwrap()
+    // 4. 接收通道中的结果（线程异常退出时，通道已关闭）
+    receiver.recv().unwrap_or_else(|e| Err(e.into()))
 }
 
 fn read_txt_from_url(
     url: &str,
-    default_port: u16
+    default_port: u16,
+    allowed_hosts: &[String]
 ) -> Result<Vec<FileData>, Box<dyn Error + Send + Sync>> {
     let (sender, receiver) = unbounded();
     let url_copy = url.to_string();
+    let allowed_hosts = allowed_hosts.to_vec();
     let mut seen_lines: Vec<String> = Vec::new();
     thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
+        let rt = match tokio::runtime::Runtime::new() {
+            Ok(rt) => rt,
+            Err(e) => {
+                let _ = sender.send(Err(e.into()));
+                return;
+            }
+        };
         let result = rt.block_on(async {
-            let response = reqwest::get(&url_copy).await?;
+            let response = build_client(&allowed_hosts)?.get(&url_copy).send().await?;
             if !response.status().is_success() {
                 return Err(format!("Failed to fetch txt from URL: {}", response.status()).into());
             }
             let body = response.text().await?;
             let mut records: Vec<FileData> = Vec::new();
+            let mut skipped = 0; // CIDR、重复和无法解析的行数
+            let mut unsupported = 0; // 无法解析的行数
             for line in body.lines() {
                 let trimmed_line = line.trim().to_string();
 
+                if trimmed_line.is_empty() {
+                    continue;
+                }
                 let contains_bool =
                     trimmed_line.contains("/") || seen_lines.contains(&trimmed_line);
-                if trimmed_line.is_empty() || contains_bool {
+                if contains_bool {
+                    skipped += 1;
                     continue;
                 }
 
@@ -210,26 +256,40 @@ fn read_txt_from_url(
                         };
                         seen_lines.push(final_line);
                         records.push(data);
+                    } else {
+                        skipped += 1;
                     }
                 } else {
-                    println!("不支持提取 `{}` 的地址和端口！", trimmed_line);
+                    skipped += 1;
+                    unsupported += 1;
                 }
             }
+            if unsupported > 0 {
+                tracing::warn!(
+                    "网络数据{}中有{}行不支持提取地址和端口，已忽略",
+                    access_log::redact_url(&url_copy),
+                    unsupported
+                );
+            }
+            metrics::record_rows("remote", records.len(), skipped);
             Ok(records)
         });
-        sender.send(result).unwrap();
+        let _ = sender.send(result);
     });
 
-    receiver.recv().unwrap()
+    receiver.recv().unwrap_or_else(|e| Err(e.into()))
 }
 
 fn process_url(
     url: &str,
-    default_port: u16
+    default_port: u16,
+    allowed_hosts: &[String]
 ) -> Result<Vec<FileData>, Box<dyn Error + Send + Sync>> {
     match url {
-        url if url.to_lowercase().ends_with(".txt") => read_txt_from_url(url, default_port),
-        url if url.to_lowercase().ends_with(".csv") => read_csv_from_url(url, default_port),
+        url if url.to_lowercase().ends_with(".txt") =>
+            read_txt_from_url(url, default_port, allowed_hosts),
+        url if url.to_lowercase().ends_with(".csv") =>
+            read_csv_from_url(url, default_port, allowed_hosts),
         _ => Err(format!("{} 不是 txt 或 csv 文件的链接", url).into()),
     }
 }
@@ -238,13 +298,20 @@ pub fn process_network_data(
     field_column: &str,
     default_port: u16,
     count: usize,
-    url: &str
-) -> Vec<MyData> {
+    url: &str,
+    allowed_hosts: &[String]
+) -> Result<Vec<MyData>, AppError> {
     let mut results: Vec<MyData> = Vec::new(); // 存储结果
     let mut seen_addr = Vec::new();
 
-    if url.to_lowercase().starts_with("https://") {
-        match process_url(url, default_port) {
+    let lower_url = url.to_lowercase();
+    if !lower_url.ends_with(".txt") && !lower_url.ends_with(".csv") {
+        // 链接可能是别名对应的链接（含有token等），不在响应和访问日志中显示
+        return Err(AppError::BadRequest("数据源不是 txt 或 csv 文件的链接".to_string()));
+    }
+
+    if lower_url.starts_with("https://") {
+        match process_url(url, default_port, allowed_hosts) {
             Ok(data) => {
                 for item in &data {
                     let addr: String = item.addr.clone();
@@ -272,6 +339,10 @@ pub fn process_network_data(
                         addr: addr.clone(),
                         port: Some(port),
                         alias: alias_prefix,
+                        colo: item.colo.clone(),
+                        loc: item.loc.clone(),
+                        region: item.region.clone(),
+                        city: item.city.clone(),
                     };
 
                     // 如果结果数量小于指定的数量，则添加数据，否则就返回，避免无意义的IO操作(读取数据)
@@ -282,9 +353,19 @@ pub fn process_network_data(
                     }
                 }
             }
-            Err(e) => eprintln!("Error: {}", e),
+            Err(e) => {
+                metrics::record_fetch_error("data");
+                // reqwest的错误信息中有完整的链接，去掉后再输出
+                let reason = match e.downcast::<reqwest::Error>() {
+                    Ok(e) => e.without_url().to_string(),
+                    Err(e) => e.to_string(),
+                };
+                tracing::warn!("获取网络数据{}失败：{}", access_log::redact_url(url), reason);
+                // 数据源的链接可能是别名对应的链接，详细的原因只在日志中输出
+                return Err(AppError::BadGateway("获取网络数据失败".to_string()));
+            }
         }
     }
 
-    results
+    Ok(results)
 }
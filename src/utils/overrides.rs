@@ -0,0 +1,518 @@
+use super::error::AppError;
+use serde_json::{json, Value as JsonValue};
+use serde_yaml::{Mapping, Value as YamlValue};
+
+/// 支持覆盖的模板设置，订阅链接中可以直接使用这些参数，也可以写成override.<名称>
+pub const OVERRIDE_KEYS: [&str; 5] = ["mixed_port", "tun", "allow_lan", "dns", "loglevel"];
+
+/// 检查覆盖参数的名称和值，返回规范化后的(名称, 值)，无效就返回400错误
+pub fn parse_override(key: &str, value: &str) -> Result<(String, String), AppError> {
+    normalize_override(key, value).map_err(AppError::BadRequest)
+}
+
+fn normalize_override(key: &str, value: &str) -> Result<(String, String), String> {
+    let key = key.to_lowercase().replace('-', "_");
+    let value = value.trim();
+    let normalized = match key.as_str() {
+        "mixed_port" => match value.parse::<u16>() {
+            Ok(port) if port > 0 => port.to_string(),
+            _ => return Err(format!("mixed_port的值{}无效，需要1~65535的端口", value)),
+        },
+        "tun" | "allow_lan" => match value.to_lowercase().as_str() {
+            "1" | "true" | "on" => "true".to_string(),
+            "0" | "false" | "off" => "false".to_string(),
+            _ => return Err(format!("{}的值{}无效，需要true或false", key, value)),
+        },
+        "dns" => {
+            let servers: Vec<&str> = value
+                .split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .collect();
+            if servers.is_empty() {
+                return Err("dns的值不能为空".to_string());
+            }
+            if let Some(invalid) = servers.iter().find(|s| !is_valid_dns_server(s)) {
+                return Err(format!(
+                    "dns服务器{}无效，需要IP地址或udp://、tcp://、tls://、https://、quic://、h3://开头的地址",
+                    invalid
+                ));
+            }
+            servers.join(",")
+        }
+        "loglevel" => match value.to_lowercase().as_str() {
+            "debug" | "info" | "error" | "silent" => value.to_lowercase(),
+            "warning" | "warn" => "warning".to_string(),
+            "none" => "silent".to_string(),
+            _ => {
+                return Err(format!(
+                    "loglevel的值{}无效，可选debug、info、warning、error、silent",
+                    value
+                ))
+            }
+        },
+        _ => {
+            return Err(format!(
+                "不支持覆盖{}，可覆盖的设置：{}",
+                key,
+                OVERRIDE_KEYS.join("、")
+            ))
+        }
+    };
+    Ok((key, normalized))
+}
+
+// DNS服务器：IP地址，或者带协议的地址（如tls://8.8.8.8、https://223.5.5.5/dns-query）
+fn is_valid_dns_server(server: &str) -> bool {
+    match server.split_once("://") {
+        Some((scheme, rest)) => {
+            ["udp", "tcp", "tls", "https", "quic", "h3"].contains(&scheme.to_lowercase().as_str())
+                && !rest.is_empty()
+                && !rest.contains(char::is_whitespace)
+        }
+        None => server.parse::<std::net::IpAddr>().is_ok(),
+    }
+}
+
+/// 覆盖clash模板中的设置
+pub fn apply_clash_overrides(
+    clash_config: &mut YamlValue,
+    overrides: &[(String, String)],
+) -> Result<(), AppError> {
+    let config = match clash_config.as_mapping_mut() {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    let invalid_field =
+        |field: &str| AppError::Internal(format!("clash模板的{}字段格式无效", field));
+    for (key, value) in overrides {
+        match key.as_str() {
+            "mixed_port" => {
+                // 使用混合端口时，删除模板中的http、socks端口，避免端口冲突
+                config.remove("port");
+                config.remove("socks-port");
+                let port = value.parse::<u16>().unwrap_or(7890);
+                config.insert("mixed-port".into(), port.into());
+            }
+            "allow_lan" => {
+                config.insert("allow-lan".into(), (value == "true").into());
+            }
+            "loglevel" => {
+                config.insert("log-level".into(), value.as_str().into());
+            }
+            "tun" => {
+                let tun = config
+                    .entry("tun".into())
+                    .or_insert(YamlValue::Mapping(Mapping::new()))
+                    .as_mapping_mut()
+                    .ok_or(invalid_field("tun"))?;
+                tun.insert("enable".into(), (value == "true").into());
+                if value == "true" {
+                    let defaults: [(&str, YamlValue); 4] = [
+                        ("stack", "mixed".into()),
+                        ("auto-route", true.into()),
+                        ("auto-detect-interface", true.into()),
+                        ("dns-hijack", YamlValue::Sequence(vec!["any:53".into()])),
+                    ];
+                    for (field, default) in defaults {
+                        tun.entry(field.into()).or_insert(default);
+                    }
+                }
+            }
+            "dns" => {
+                let dns = config
+                    .entry("dns".into())
+                    .or_insert(YamlValue::Mapping(Mapping::new()))
+                    .as_mapping_mut()
+                    .ok_or(invalid_field("dns"))?;
+                let servers = value.split(',').map(YamlValue::from).collect();
+                dns.insert("enable".into(), true.into());
+                dns.insert("nameserver".into(), YamlValue::Sequence(servers));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// 覆盖sing-box模板中的设置（在迁移为新版本的写法之前调用，这里使用旧版的写法）
+pub fn apply_singbox_overrides(
+    singbox_config: &mut JsonValue,
+    overrides: &[(String, String)],
+) -> Result<(), AppError> {
+    let config = match singbox_config.as_object_mut() {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    let invalid_field =
+        |field: &str| AppError::Internal(format!("sing-box模板的{}字段格式无效", field));
+    for (key, value) in overrides {
+        match key.as_str() {
+            "mixed_port" | "allow_lan" => {
+                // 只修改模板中已有的mixed入站，模板没有mixed入站就不添加
+                let inbounds = match config.get_mut("inbounds") {
+                    Some(inbounds) => inbounds.as_array_mut().ok_or(invalid_field("inbounds"))?,
+                    None => continue,
+                };
+                let mut mixed_inbounds = inbounds
+                    .iter_mut()
+                    .filter(|x| x["type"] == "mixed")
+                    .peekable();
+                if mixed_inbounds.peek().is_none() {
+                    tracing::warn!("sing-box模板中没有mixed入站，已忽略{}", key);
+                }
+                for inbound in mixed_inbounds {
+                    match key.as_str() {
+                        "mixed_port" => {
+                            let port = value.parse::<u16>().unwrap_or(2080);
+                            inbound["listen_port"] = json!(port);
+                        }
+                        _ => {
+                            let listen = match value.as_str() {
+                                "true" => "0.0.0.0",
+                                _ => "127.0.0.1",
+                            };
+                            inbound["listen"] = json!(listen);
+                        }
+                    }
+                }
+            }
+            "tun" => {
+                let inbounds = config
+                    .entry("inbounds")
+                    .or_insert(json!([]))
+                    .as_array_mut()
+                    .ok_or(invalid_field("inbounds"))?;
+                match value.as_str() {
+                    "true" => {
+                        if !inbounds.iter().any(|x| x["type"] == "tun") {
+                            inbounds.insert(
+                                0,
+                                json!({
+                                    "type": "tun",
+                                    "tag": "tun-in",
+                                    "inet4_address": "172.19.0.1/30",
+                                    "auto_route": true,
+                                    "strict_route": true,
+                                    "sniff": true,
+                                    "stack": "system"
+                                }),
+                            );
+                        }
+                    }
+                    _ => inbounds.retain(|x| x["type"] != "tun"),
+                }
+            }
+            "loglevel" => {
+                match value.as_str() {
+                    "silent" => {
+                        config.insert("log".to_string(), json!({ "disabled": true }));
+                    }
+                    level => {
+                        let log = config.entry("log").or_insert(json!({}));
+                        if !log.is_object() {
+                            *log = json!({});
+                        }
+                        let level = match level {
+                            "warning" => "warn",
+                            level => level,
+                        };
+                        log["level"] = json!(level);
+                        log["disabled"] = json!(false);
+                    }
+                };
+            }
+            "dns" => {
+                let dns = config.entry("dns").or_insert(json!({}));
+                let dns = dns.as_object_mut().ok_or(invalid_field("dns"))?;
+                replace_singbox_dns_servers(dns, value)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// 替换模板中的普通DNS服务器（跟clash的nameserver一样替换整个列表），local、fakeip、rcode://、dhcp://保留。
+// 第N个新服务器对应模板中第N个被替换的服务器，使用它的detour，DNS规则、final和address_resolver中对它的引用改为这个新服务器，
+// 这样分流的DNS（如国内直连、国外走代理）仍然分流；新服务器较少时，其余被替换服务器的引用改为第一个新服务器
+fn replace_singbox_dns_servers(
+    dns: &mut serde_json::Map<String, JsonValue>,
+    value: &str,
+) -> Result<(), AppError> {
+    let servers = dns
+        .entry("servers")
+        .or_insert(json!([]))
+        .as_array_mut()
+        .ok_or(AppError::Internal(
+            "sing-box模板的dns.servers字段格式无效".to_string(),
+        ))?;
+    let is_regular = |server: &JsonValue| {
+        let address = server["address"].as_str().unwrap_or_default();
+        !["local", "fakeip"].contains(&address)
+            && !address.starts_with("rcode://")
+            && !address.starts_with("dhcp://")
+    };
+    let replaced: Vec<&JsonValue> = servers.iter().filter(|server| is_regular(server)).collect();
+    let new_servers: Vec<JsonValue> = value
+        .split(',')
+        .enumerate()
+        .map(|(index, address)| {
+            let mut server = json!({
+                "tag": format!("dns-override-{}", index + 1),
+                "address": address
+            });
+            // 新服务器比被替换的多时，多出的使用第一个被替换服务器的detour
+            let detour = replaced
+                .get(index)
+                .or(replaced.first())
+                .and_then(|old| old.get("detour"));
+            if let Some(detour) = detour {
+                server["detour"] = detour.clone();
+            }
+            server
+        })
+        .collect();
+    // 被替换服务器的tag -> 替换它的新服务器的tag
+    let renamed: Vec<(String, String)> = replaced
+        .iter()
+        .enumerate()
+        .filter_map(|(index, server)| {
+            let new_index = match index < new_servers.len() {
+                true => index + 1,
+                false => 1,
+            };
+            server["tag"]
+                .as_str()
+                .map(|tag| (tag.to_string(), format!("dns-override-{}", new_index)))
+        })
+        .collect();
+    servers.retain(|server| !is_regular(server));
+    servers.splice(0..0, new_servers);
+
+    let rename = |x: &mut JsonValue| {
+        let new_tag = renamed
+            .iter()
+            .find(|(old, _)| x.as_str() == Some(old.as_str()))
+            .map(|(_, new)| new.clone());
+        if let Some(new_tag) = new_tag {
+            *x = json!(new_tag);
+        }
+    };
+    for server in servers.iter_mut() {
+        if let Some(resolver) = server.get_mut("address_resolver") {
+            rename(resolver);
+        }
+    }
+    if let Some(rules) = dns.get_mut("rules").and_then(JsonValue::as_array_mut) {
+        for rule in rules.iter_mut() {
+            if let Some(server) = rule.get_mut("server") {
+                rename(server);
+            }
+        }
+    }
+    if let Some(dns_final) = dns.get_mut("final") {
+        rename(dns_final);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items
+            .iter()
+            .map(|(key, value)| parse_override(key, value).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn override_values_are_normalized_or_rejected() {
+        assert_eq!(
+            parse_override("Mixed-Port", " 7890 ").unwrap(),
+            ("mixed_port".to_string(), "7890".to_string())
+        );
+        assert_eq!(parse_override("tun", "on").unwrap().1, "true");
+        assert_eq!(parse_override("loglevel", "WARN").unwrap().1, "warning");
+        assert_eq!(
+            parse_override("dns", "tls://1.1.1.1, 223.5.5.5,")
+                .unwrap()
+                .1,
+            "tls://1.1.1.1,223.5.5.5"
+        );
+        for (key, value) in [
+            ("mixed_port", "0"),
+            ("mixed_port", "70000"),
+            ("tun", "maybe"),
+            ("dns", ""),
+            ("dns", "ftp://1.1.1.1"),
+            ("dns", "example.com"),
+            ("loglevel", "verbose"),
+            ("port", "7890"),
+        ] {
+            assert!(
+                matches!(parse_override(key, value), Err(AppError::BadRequest(_))),
+                "{}={}",
+                key,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn clash_overrides_replace_settings() {
+        let mut config: YamlValue =
+            serde_yaml::from_str("port: 7890\nsocks-port: 7891\ndns: { nameserver: [8.8.8.8] }")
+                .unwrap();
+        apply_clash_overrides(
+            &mut config,
+            &overrides(&[("mixed_port", "7000"), ("dns", "1.1.1.1"), ("tun", "true")]),
+        )
+        .unwrap();
+        assert!(config.get("port").is_none() && config.get("socks-port").is_none());
+        assert_eq!(config["mixed-port"], 7000);
+        assert_eq!(
+            config["dns"]["nameserver"],
+            serde_yaml::from_str::<YamlValue>("[1.1.1.1]").unwrap()
+        );
+        assert_eq!(config["tun"]["enable"], true);
+        assert_eq!(config["tun"]["stack"], "mixed");
+    }
+
+    #[test]
+    fn clash_invalid_template_fields_are_errors() {
+        let mut config: YamlValue = serde_yaml::from_str("tun: true\ndns: [8.8.8.8]").unwrap();
+        for (key, value) in [("tun", "true"), ("dns", "1.1.1.1")] {
+            let result = apply_clash_overrides(&mut config, &overrides(&[(key, value)]));
+            assert!(matches!(result, Err(AppError::Internal(_))), "{}", key);
+        }
+    }
+
+    #[test]
+    fn singbox_mixed_overrides_only_change_existing_inbounds() {
+        let mut config = json!({ "inbounds": [{ "type": "tun", "tag": "tun-in" }] });
+        apply_singbox_overrides(
+            &mut config,
+            &overrides(&[("mixed_port", "7000"), ("allow_lan", "true")]),
+        )
+        .unwrap();
+        assert_eq!(
+            config["inbounds"],
+            json!([{ "type": "tun", "tag": "tun-in" }])
+        );
+
+        let mut config = json!({ "inbounds": [{ "type": "mixed", "listen_port": 2080 }] });
+        apply_singbox_overrides(
+            &mut config,
+            &overrides(&[("mixed_port", "7000"), ("allow_lan", "true")]),
+        )
+        .unwrap();
+        assert_eq!(config["inbounds"][0]["listen_port"], 7000);
+        assert_eq!(config["inbounds"][0]["listen"], "0.0.0.0");
+    }
+
+    #[test]
+    fn singbox_invalid_template_fields_are_errors() {
+        let mut config = json!({ "inbounds": {}, "dns": [] });
+        for key in ["mixed_port", "tun", "dns"] {
+            let value = match key {
+                "dns" => "1.1.1.1",
+                "tun" => "true",
+                _ => "7000",
+            };
+            let result = apply_singbox_overrides(&mut config, &overrides(&[(key, value)]));
+            assert!(matches!(result, Err(AppError::Internal(_))), "{}", key);
+        }
+    }
+
+    #[test]
+    fn singbox_dns_override_replaces_regular_servers() {
+        let mut config = json!({
+            "dns": {
+                "servers": [
+                    { "tag": "remote", "address": "tls://8.8.8.8", "detour": "proxy" },
+                    { "tag": "local", "address": "223.5.5.5", "detour": "direct" },
+                    { "tag": "block", "address": "rcode://success" },
+                    { "tag": "resolver", "address": "local" }
+                ],
+                "rules": [
+                    { "geosite": "cn", "server": "local" },
+                    { "geosite": "ads", "server": "block" }
+                ],
+                "final": "remote",
+                "strategy": "ipv4_only"
+            }
+        });
+        let original = config.clone();
+        apply_singbox_overrides(&mut config, &overrides(&[("dns", "tls://9.9.9.9,1.1.1.1")]))
+            .unwrap();
+        let dns = &config["dns"];
+        // 分流的DNS保持不变：远程服务器走代理，国内服务器直连
+        assert_eq!(
+            dns["servers"],
+            json!([
+                { "tag": "dns-override-1", "address": "tls://9.9.9.9", "detour": "proxy" },
+                { "tag": "dns-override-2", "address": "1.1.1.1", "detour": "direct" },
+                { "tag": "block", "address": "rcode://success" },
+                { "tag": "resolver", "address": "local" }
+            ])
+        );
+        assert_eq!(dns["rules"][0]["server"], "dns-override-2");
+        assert_eq!(dns["rules"][1]["server"], "block");
+        assert_eq!(dns["final"], "dns-override-1");
+        assert_eq!(dns["strategy"], "ipv4_only");
+
+        // 只有一个新服务器时，所有引用都改为它，使用第一个被替换服务器的detour
+        let mut config = original;
+        apply_singbox_overrides(&mut config, &overrides(&[("dns", "1.1.1.1")])).unwrap();
+        let dns = &config["dns"];
+        assert_eq!(
+            dns["servers"][0],
+            json!({ "tag": "dns-override-1", "address": "1.1.1.1", "detour": "proxy" })
+        );
+        assert_eq!(dns["servers"].as_array().unwrap().len(), 3);
+        assert_eq!(dns["rules"][0]["server"], "dns-override-1");
+        assert_eq!(dns["final"], "dns-override-1");
+    }
+
+    #[test]
+    fn singbox_dns_override_without_detour_adds_none() {
+        let mut config = json!({
+            "dns": {
+                "servers": [
+                    { "tag": "google", "address": "tls://8.8.8.8" },
+                    { "tag": "doh", "address": "https://dns.google/dns-query", "address_resolver": "google" }
+                ]
+            }
+        });
+        apply_singbox_overrides(
+            &mut config,
+            &overrides(&[("dns", "1.1.1.1,8.8.4.4,9.9.9.9")]),
+        )
+        .unwrap();
+        assert_eq!(
+            config["dns"]["servers"],
+            json!([
+                { "tag": "dns-override-1", "address": "1.1.1.1" },
+                { "tag": "dns-override-2", "address": "8.8.4.4" },
+                { "tag": "dns-override-3", "address": "9.9.9.9" }
+            ])
+        );
+    }
+
+    #[test]
+    fn singbox_dns_override_keeps_other_dns_settings() {
+        let mut config = json!({ "dns": { "final": "x", "strategy": "prefer_ipv4" } });
+        apply_singbox_overrides(&mut config, &overrides(&[("dns", "1.1.1.1")])).unwrap();
+        assert_eq!(
+            config["dns"],
+            json!({
+                "servers": [{ "tag": "dns-override-1", "address": "1.1.1.1" }],
+                "final": "x",
+                "strategy": "prefer_ipv4"
+            })
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use super::{error::AppError, server_config::ServerConfig};
+use lazy_static::lazy_static;
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::{fs, sync::Mutex};
+
+lazy_static! {
+    // 通过管理接口修改profiles文件时加锁，避免同时写入
+    static ref PROFILES_FILE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// 保存的订阅配置：名称、订阅链接的参数（跟/sub的参数一样）、是否可以通过管理接口修改
+pub struct Profile {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub editable: bool,
+}
+
+/// 检查配置的名称：只能使用字母、数字、-和_，最多64个字符
+pub fn check_profile_name(name: &str) -> Result<(), AppError> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    match valid {
+        true => Ok(()),
+        false => Err(AppError::BadRequest(format!(
+            "配置名称{}无效，只能使用字母、数字、-和_",
+            name
+        ))),
+    }
+}
+
+/// 将{ 参数名: 值 }转换为参数列表，列表的值用逗号连接（如httpsPorts: [443, 2053]），
+/// token不能保存在配置中（访问时仍然需要token），会被忽略
+pub fn parse_profile_params(value: &YamlValue) -> Vec<(String, String)> {
+    let mapping = match value.as_mapping() {
+        Some(mapping) => mapping,
+        None => return Vec::new(),
+    };
+    mapping
+        .iter()
+        .filter_map(|(key, value)| {
+            let key = key.as_str()?.trim().to_string();
+            if key.to_lowercase() == "token" {
+                tracing::warn!("订阅配置中的token参数无效，已忽略");
+                return None;
+            }
+            let value = match value {
+                YamlValue::Sequence(seq) => seq
+                    .iter()
+                    .filter_map(scalar_to_string)
+                    .collect::<Vec<String>>()
+                    .join(","),
+                value => scalar_to_string(value)?,
+            };
+            Some((key, value))
+        })
+        .collect()
+}
+
+fn scalar_to_string(value: &YamlValue) -> Option<String> {
+    match value {
+        YamlValue::String(s) => Some(s.clone()),
+        YamlValue::Number(n) => Some(n.to_string()),
+        YamlValue::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// 所有的订阅配置，server.yaml中的在前（不能通过管理接口修改），profiles文件中的在后
+pub fn list_profiles(server_config: &ServerConfig) -> Vec<Profile> {
+    let mut profiles: Vec<Profile> = server_config
+        .profiles
+        .iter()
+        .map(|(name, params)| Profile {
+            name: name.clone(),
+            params: params.clone(),
+            editable: false,
+        })
+        .collect();
+    for (name, value) in read_profiles_file(&server_config.profiles_file) {
+        let name = name.as_str().unwrap_or_default().to_string();
+        if check_profile_name(&name).is_ok() && !profiles.iter().any(|p| p.name == name) {
+            profiles.push(Profile {
+                params: parse_profile_params(&value),
+                name,
+                editable: true,
+            });
+        }
+    }
+    profiles
+}
+
+/// 查找指定名称的订阅配置
+pub fn find_profile(name: &str, server_config: &ServerConfig) -> Option<Profile> {
+    list_profiles(server_config)
+        .into_iter()
+        .find(|profile| profile.name == name)
+}
+
+/// 保存（新建或修改）profiles文件中的订阅配置，返回是否为新建的配置
+pub fn save_profile(
+    name: &str,
+    params: &[(String, String)],
+    server_config: &ServerConfig,
+) -> Result<bool, AppError> {
+    check_profile_name(name)?;
+    if server_config.profiles.iter().any(|(n, _)| n == name) {
+        return Err(AppError::Forbidden(format!(
+            "配置{}在服务器配置中，不能通过管理接口修改",
+            name
+        )));
+    }
+    let _lock = PROFILES_FILE_LOCK.lock();
+    let mut profiles = read_profiles_file(&server_config.profiles_file);
+    let mut mapping = Mapping::new();
+    for (key, value) in params {
+        mapping.insert(
+            YamlValue::from(key.as_str()),
+            YamlValue::from(value.as_str()),
+        );
+    }
+    let created = profiles
+        .insert(YamlValue::from(name), YamlValue::Mapping(mapping))
+        .is_none();
+    write_profiles_file(&server_config.profiles_file, &profiles)?;
+    Ok(created)
+}
+
+/// 删除profiles文件中的订阅配置
+pub fn delete_profile(name: &str, server_config: &ServerConfig) -> Result<(), AppError> {
+    check_profile_name(name)?;
+    if server_config.profiles.iter().any(|(n, _)| n == name) {
+        return Err(AppError::Forbidden(format!(
+            "配置{}在服务器配置中，不能通过管理接口删除",
+            name
+        )));
+    }
+    let _lock = PROFILES_FILE_LOCK.lock();
+    let mut profiles = read_profiles_file(&server_config.profiles_file);
+    if profiles.remove(name).is_none() {
+        return Err(AppError::NotFound(format!("没有名称为{}的配置", name)));
+    }
+    write_profiles_file(&server_config.profiles_file, &profiles)
+}
+
+// 文件不存在或者无法解析时，视为没有配置
+fn read_profiles_file(file_path: &str) -> Mapping {
+    fs::read_to_string(file_path)
+        .ok()
+        .and_then(|content| serde_yaml::from_str::<YamlValue>(&content).ok())
+        .and_then(|value| value.as_mapping().cloned())
+        .unwrap_or_default()
+}
+
+// 先写入临时文件再重命名，避免写入一半时被读取
+fn write_profiles_file(file_path: &str, profiles: &Mapping) -> Result<(), AppError> {
+    let content = serde_yaml::to_string(profiles).unwrap_or_default();
+    let temp_path = format!("{}.tmp", file_path);
+    fs::write(&temp_path, content)
+        .and_then(|_| fs::rename(&temp_path, file_path))
+        .map_err(|e| {
+            tracing::error!("保存订阅配置到{}失败：{}", file_path, e);
+            AppError::Internal("保存订阅配置失败".to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    // profiles文件放在临时目录中，server.yaml中有一个不能修改的配置fixed
+    fn profiles_config(name: &str) -> ServerConfig {
+        let dir =
+            std::env::temp_dir().join(format!("cfwks-profiles-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        ServerConfig {
+            profiles: vec![("fixed".to_string(), pairs(&[("target", "clash")]))],
+            profiles_file: dir.join("profiles.yaml").to_string_lossy().to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn profile_names_are_checked() {
+        for name in ["home", "home-clash_2", &"a".repeat(64)] {
+            assert!(check_profile_name(name).is_ok(), "{}", name);
+        }
+        for name in [
+            "",
+            "../x",
+            "a/b",
+            "a.b",
+            "..",
+            "a b",
+            "名称",
+            &"a".repeat(65),
+        ] {
+            assert!(
+                matches!(check_profile_name(name), Err(AppError::BadRequest(_))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn params_are_flattened_and_token_ignored() {
+        let value: YamlValue = serde_yaml::from_str(
+            "{ target: clash, id: 2, tls: true, https_ports: [443, 2053], token: secret }",
+        )
+        .unwrap();
+        assert_eq!(
+            parse_profile_params(&value),
+            pairs(&[
+                ("target", "clash"),
+                ("id", "2"),
+                ("tls", "true"),
+                ("https_ports", "443,2053")
+            ])
+        );
+        assert!(parse_profile_params(&YamlValue::from("clash")).is_empty());
+    }
+
+    #[test]
+    fn profiles_are_saved_listed_and_deleted() {
+        let config = profiles_config("roundtrip");
+        let params = pairs(&[("target", "singbox"), ("n", "50")]);
+        assert!(save_profile("phone", &params, &config).unwrap());
+        assert!(!save_profile("phone", &pairs(&[("target", "clash")]), &config).unwrap());
+        assert!(save_profile("laptop", &params, &config).unwrap());
+
+        let listed: Vec<(String, bool)> = list_profiles(&config)
+            .into_iter()
+            .map(|p| (p.name, p.editable))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("fixed".to_string(), false),
+                ("phone".to_string(), true),
+                ("laptop".to_string(), true)
+            ]
+        );
+        assert_eq!(
+            find_profile("phone", &config).unwrap().params,
+            pairs(&[("target", "clash")])
+        );
+
+        delete_profile("phone", &config).unwrap();
+        assert!(find_profile("phone", &config).is_none());
+        assert!(matches!(
+            delete_profile("phone", &config),
+            Err(AppError::NotFound(_))
+        ));
+        assert!(find_profile("laptop", &config).is_some());
+        std::fs::remove_dir_all(
+            std::path::Path::new(&config.profiles_file)
+                .parent()
+                .unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn invalid_and_server_profiles_are_not_changed() {
+        let config = profiles_config("rejected");
+        let params = pairs(&[("target", "clash")]);
+        for name in ["../x", "../../etc/passwd", "a/b", ""] {
+            assert!(
+                matches!(
+                    save_profile(name, &params, &config),
+                    Err(AppError::BadRequest(_))
+                ),
+                "{}",
+                name
+            );
+            assert!(
+                matches!(delete_profile(name, &config), Err(AppError::BadRequest(_))),
+                "{}",
+                name
+            );
+        }
+        assert!(matches!(
+            save_profile("fixed", &params, &config),
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            delete_profile("fixed", &config),
+            Err(AppError::Forbidden(_))
+        ));
+        // 没有写入过profiles文件
+        assert!(!std::path::Path::new(&config.profiles_file).exists());
+
+        // profiles文件中名称无效或者跟server.yaml重名的配置会被忽略
+        std::fs::write(
+            &config.profiles_file,
+            "fixed: { target: v2ray }\n'../x': { target: v2ray }\nok: {}\n",
+        )
+        .unwrap();
+        let names: Vec<String> = list_profiles(&config).into_iter().map(|p| p.name).collect();
+        assert_eq!(names, ["fixed", "ok"]);
+        assert_eq!(find_profile("fixed", &config).unwrap().params, params);
+        std::fs::remove_dir_all(
+            std::path::Path::new(&config.profiles_file)
+                .parent()
+                .unwrap(),
+        )
+        .unwrap();
+    }
+}
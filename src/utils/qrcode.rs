@@ -4,11 +4,19 @@ use qrcode::QrCode;
 use std::io::Cursor;
 /// 生成包含二维码和内容的 HTML 页面
 pub fn generate_html_with_qrcode(content: &str, url: &str) -> String {
-    let code = QrCode::new(url).unwrap();
+    // 链接太长时无法生成二维码，只显示内容
+    let code = match QrCode::new(url) {
+        Ok(code) => code,
+        Err(_) => {
+            return format!("<pre>{}</pre>", content);
+        }
+    };
     // 渲染二维码为图像并转换为Base64字符串
     let image = code.render::<Luma<u8>>().build();
     let mut buffer = Cursor::new(Vec::new());
-    image.write_to(&mut buffer, ImageFormat::Png).unwrap();
+    if image.write_to(&mut buffer, ImageFormat::Png).is_err() {
+        return format!("<pre>{}</pre>", content);
+    }
     let base64_qrcode = encode(buffer.get_ref());
 
     // 构建HTML内容
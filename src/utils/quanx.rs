@@ -0,0 +1,208 @@
+use super::{config::get_yaml_value, tls::TlsOptions};
+use serde_yaml::Value as YamlValue;
+
+/// 检查节点能否转换为Quantumult X的代理，不能转换就返回原因
+pub fn check_quanx_node(node_type: &str, yaml_value: &YamlValue) -> Result<(), String> {
+    match node_type {
+        "vless" | "trojan" | "vmess" => Ok(()),
+        "ss" => {
+            let plugin = get_yaml_value(yaml_value, &["plugin"])
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+            match plugin {
+                "" | "obfs" | "v2ray-plugin" => Ok(()),
+                _ => Err(format!("不支持ss的{}插件", plugin)),
+            }
+        }
+        _ => Err(format!("不支持{}协议", node_type)),
+    }
+}
+
+/// 构建Quantumult X的[server_local]代理行，返回的前面是节点名称，后面是代理行
+pub fn build_quanx_proxy(
+    proxy_type: &str,
+    yaml_value: &mut YamlValue,
+    remarks: String,
+    server_address: String,
+    server_port: u16,
+) -> (String, String) {
+    if check_quanx_node(proxy_type, yaml_value).is_err() {
+        return (String::new(), String::new());
+    }
+    // 节点名称中的逗号、等号会破坏配置文件的格式
+    let remarks = remarks.replace([',', '='], " ");
+
+    let get_str = |keys: &[&str]| -> String {
+        get_yaml_value(yaml_value, keys)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let tls = TlsOptions::from_yaml(yaml_value, proxy_type);
+
+    let (protocol, mut fields) = match proxy_type {
+        "vless" => (
+            "vless",
+            vec![
+                "method=none".to_string(),
+                format!("password={}", get_str(&["uuid"])),
+            ],
+        ),
+        "vmess" => {
+            // Quantumult X不支持auto加密方式
+            let cipher = match get_str(&["cipher"]).as_str() {
+                "" | "auto" => "chacha20-ietf-poly1305".to_string(),
+                cipher => cipher.to_string(),
+            };
+            (
+                "vmess",
+                vec![
+                    format!("method={}", cipher),
+                    format!("password={}", get_str(&["uuid"])),
+                    "aead=true".to_string(),
+                ],
+            )
+        }
+        "trojan" => (
+            "trojan",
+            vec![format!("password={}", get_str(&["password"]))],
+        ),
+        _ => (
+            "shadowsocks",
+            vec![
+                format!("method={}", get_str(&["cipher"])),
+                format!("password={}", get_str(&["password"])),
+            ],
+        ),
+    };
+
+    // 传输层：ws/wss用obfs、obfs-host、obfs-uri表示
+    let (network, host, path) = match proxy_type {
+        "ss" => match get_str(&["plugin"]).as_str() {
+            "v2ray-plugin" => (
+                "ws".to_string(),
+                get_str(&["plugin-opts", "host"]),
+                get_str(&["plugin-opts", "path"]),
+            ),
+            "obfs" => (
+                get_str(&["plugin-opts", "mode"]),
+                get_str(&["plugin-opts", "host"]),
+                String::new(),
+            ),
+            _ => (String::new(), String::new(), String::new()),
+        },
+        _ => (
+            get_str(&["network"]),
+            get_str(&["ws-opts", "headers", "Host"]),
+            get_str(&["ws-opts", "path"]),
+        ),
+    };
+    match network.as_str() {
+        "ws" => {
+            let obfs = match tls.enabled {
+                true => "wss",
+                false => "ws",
+            };
+            fields.push(format!("obfs={}", obfs));
+        }
+        "http" | "tls" => fields.push(format!("obfs={}", network)),
+        _ => {
+            // 没有传输层设置的trojan、vless，通过over-tls开启TLS
+            if tls.enabled {
+                fields.push("over-tls=true".to_string());
+            }
+        }
+    }
+    if !host.is_empty() && !network.is_empty() {
+        fields.push(format!("obfs-host={}", host));
+    }
+    if !path.is_empty() && network == "ws" {
+        fields.push(format!("obfs-uri={}", path));
+    }
+    if tls.enabled && proxy_type != "ss" {
+        if !tls.server_name.is_empty() {
+            fields.push(format!("tls-host={}", tls.server_name));
+        }
+        fields.push(format!("tls-verification={}", !tls.skip_cert_verify));
+    }
+    fields.push("fast-open=false".to_string());
+    fields.push("udp-relay=false".to_string());
+    fields.push(format!("tag={}", remarks));
+
+    let quanx_proxy = format!(
+        "{}={}:{}, {}",
+        protocol,
+        server_address,
+        server_port,
+        fields.join(", ")
+    );
+
+    (remarks, quanx_proxy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(proxy_type: &str, yaml: &str) -> (String, String) {
+        let mut yaml_value: YamlValue = serde_yaml::from_str(yaml).unwrap();
+        build_quanx_proxy(
+            proxy_type,
+            &mut yaml_value,
+            "US, LAX".to_string(),
+            "1.1.1.1".to_string(),
+            443,
+        )
+    }
+
+    #[test]
+    fn unsupported_nodes_are_skipped() {
+        let yaml_value: YamlValue = serde_yaml::from_str("plugin: shadow-tls").unwrap();
+        assert!(check_quanx_node("ss", &yaml_value).is_err());
+        assert!(check_quanx_node("hysteria2", &yaml_value).is_err());
+        assert_eq!(
+            build("ss", "plugin: shadow-tls"),
+            (String::new(), String::new())
+        );
+    }
+
+    #[test]
+    fn vmess_ws_tls_proxy_line() {
+        let (name, line) = build(
+            "vmess",
+            "{ uuid: abc, cipher: auto, network: ws, sni: a.example.com, skip-cert-verify: false, \
+             ws-opts: { path: /ws, headers: { Host: a.example.com } } }",
+        );
+        assert_eq!(name, "US  LAX");
+        assert_eq!(
+            line,
+            "vmess=1.1.1.1:443, method=chacha20-ietf-poly1305, password=abc, aead=true, obfs=wss, \
+             obfs-host=a.example.com, obfs-uri=/ws, tls-host=a.example.com, tls-verification=true, \
+             fast-open=false, udp-relay=false, tag=US  LAX"
+        );
+    }
+
+    #[test]
+    fn trojan_without_transport_uses_over_tls() {
+        let (_, line) = build("trojan", "{ password: pw, sni: a.example.com }");
+        assert_eq!(
+            line,
+            "trojan=1.1.1.1:443, password=pw, over-tls=true, tls-host=a.example.com, \
+             tls-verification=false, fast-open=false, udp-relay=false, tag=US  LAX"
+        );
+    }
+
+    #[test]
+    fn ss_v2ray_plugin_uses_ws_obfs() {
+        let (_, line) = build(
+            "ss",
+            "{ cipher: aes-128-gcm, password: pw, plugin: v2ray-plugin, \
+             plugin-opts: { mode: websocket, host: a.example.com, path: /ss } }",
+        );
+        assert_eq!(
+            line,
+            "shadowsocks=1.1.1.1:443, method=aes-128-gcm, password=pw, obfs=wss, \
+             obfs-host=a.example.com, obfs-uri=/ss, fast-open=false, udp-relay=false, tag=US  LAX"
+        );
+    }
+}
@@ -0,0 +1,502 @@
+use super::auth::{self, AccessToken};
+use super::config::get_yaml_value;
+use super::error::AppError;
+use super::profile;
+use reqwest::Url;
+use serde_yaml::Value as YamlValue;
+use std::{fs, path::PathBuf};
+
+/// 支持的转换目标（检查User-Agent映射表的target）
+pub const TARGETS: [&str; 9] = [
+    "v2ray",
+    "singbox",
+    "xray",
+    "clash",
+    "clash-provider",
+    "surge",
+    "surfboard",
+    "quanx",
+    "loon",
+];
+
+/// 内置的User-Agent映射表（target=auto时使用），按顺序匹配User-Agent中的关键字（不区分大小写），
+/// Hiddify的User-Agent同时含有ClashMeta、sing-box，需要放在最前面
+const DEFAULT_USER_AGENTS: [(&str, &str); 22] = [
+    ("hiddify", "singbox"),
+    ("sing-box", "singbox"),
+    ("sfa/", "singbox"),
+    ("sfi/", "singbox"),
+    ("sfm/", "singbox"),
+    ("sft/", "singbox"),
+    ("clash-verge", "clash"),
+    ("clashmetaforandroid", "clash"),
+    ("clash.meta", "clash"),
+    ("flclash", "clash"),
+    ("mihomo", "clash"),
+    ("stash", "clash"),
+    ("clash", "clash"),
+    ("surfboard", "surfboard"),
+    ("surge", "surge"),
+    ("quantumult x", "quanx"),
+    ("loon", "loon"),
+    ("v2rayng", "v2ray"),
+    ("v2rayn", "v2ray"),
+    ("shadowrocket", "v2ray"),
+    ("nekobox", "v2ray"),
+    ("xray", "xray"),
+];
+
+/// 服务器配置（默认为server.yaml），每次请求时读取，修改后不需要重启程序；
+/// 文件不存在或者字段没有设置，就使用默认值
+#[derive(Clone)]
+pub struct ServerConfig {
+    pub template_hosts: Vec<String>, // 允许获取远程模板的主机，为空就不允许使用远程模板
+    pub template_cache_ttl: u64,     // 远程模板的缓存时间（秒）
+    pub template_max_size: usize,    // 远程模板的最大字节数
+    pub profile_name: String,        // 订阅文件名（Content-Disposition）的前缀
+    pub update_interval: u64,        // 客户端自动更新订阅的间隔（小时），0为不发送
+    pub web_page_url: String,        // 订阅的主页
+    pub userinfo: String,            // 流量和到期时间（subscription-userinfo），为空就不发送
+    pub user_agents: Vec<(String, String)>, // User-Agent关键字和转换目标，自定义的在内置的前面
+    pub tokens: Vec<AccessToken>,    // 订阅的访问令牌，为空就不需要令牌
+    pub data_roots: Vec<String>,     // source参数允许使用的本地数据目录
+    pub data_hosts: Vec<String>,     // source参数允许使用的网络数据主机，为空就不允许网络数据源
+    pub data_aliases: Vec<(String, String)>, // 数据源的别名（source=<别名>），由服务器配置，不受上面的限制
+    pub profiles: Vec<(String, Vec<(String, String)>)>, // 保存的订阅配置（/s/<名称>），不能通过管理接口修改
+    pub profiles_file: String,                          // 通过管理接口保存的订阅配置的文件
+    pub admin_token: String,                            // 管理接口的令牌，为空就禁用管理接口
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            template_hosts: Vec::new(),
+            template_cache_ttl: 600,
+            template_max_size: 1024 * 1024,
+            profile_name: "cfwks".to_string(),
+            update_interval: 24,
+            web_page_url: String::new(),
+            userinfo: String::new(),
+            user_agents: default_user_agents(),
+            tokens: Vec::new(),
+            data_roots: vec!["./data".to_string()],
+            data_hosts: Vec::new(),
+            data_aliases: Vec::new(),
+            profiles: Vec::new(),
+            profiles_file: "profiles.yaml".to_string(),
+            admin_token: String::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn load(file_path: &str) -> Self {
+        let yaml_value: YamlValue = std::fs::read_to_string(file_path)
+            .ok()
+            .and_then(|content| serde_yaml::from_str(&content).ok())
+            .unwrap_or(YamlValue::Null);
+        let default = ServerConfig::default();
+
+        let template_hosts = get_yaml_value(&yaml_value, &["remote_templates", "allowed_hosts"])
+            .and_then(|v| v.as_sequence())
+            .map(|seq| {
+                seq.iter()
+                    .filter_map(|v| v.as_str())
+                    .map(|s| s.trim().to_lowercase())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or(default.template_hosts);
+        let template_cache_ttl = get_yaml_value(&yaml_value, &["remote_templates", "cache_ttl"])
+            .and_then(|v| v.as_u64())
+            .unwrap_or(default.template_cache_ttl);
+        let template_max_size = get_yaml_value(&yaml_value, &["remote_templates", "max_size"])
+            .and_then(|v| v.as_u64())
+            .map_or(default.template_max_size, |size| size as usize);
+
+        let get_str = |keys: &[&str], default: String| {
+            get_yaml_value(&yaml_value, keys)
+                .and_then(|v| v.as_str())
+                .map_or(default, |s| s.trim().to_string())
+        };
+        let profile_name = get_str(&["subscription", "profile_name"], default.profile_name);
+        let web_page_url = get_str(&["subscription", "web_page_url"], default.web_page_url);
+        let update_interval = get_yaml_value(&yaml_value, &["subscription", "update_interval"])
+            .and_then(|v| v.as_u64())
+            .unwrap_or(default.update_interval);
+        let userinfo = get_yaml_value(&yaml_value, &["subscription", "userinfo"])
+            .map(format_userinfo)
+            .unwrap_or(default.userinfo);
+
+        // 自定义的User-Agent映射，缺少字段或者target不支持的规则会被忽略
+        let mut user_agents: Vec<(String, String)> = get_yaml_value(&yaml_value, &["user_agents"])
+            .and_then(|v| v.as_sequence())
+            .map(|seq| seq.iter().filter_map(parse_user_agent_rule).collect())
+            .unwrap_or_default();
+        user_agents.extend(default.user_agents);
+
+        let tokens = get_yaml_value(&yaml_value, &["auth", "tokens"])
+            .and_then(|v| v.as_sequence())
+            .map(|seq| {
+                seq.iter()
+                    .filter_map(|value| {
+                        let userinfo = get_yaml_value(value, &["userinfo"])
+                            .map(format_userinfo)
+                            .unwrap_or_default();
+                        auth::parse_access_token(value, userinfo)
+                    })
+                    .collect()
+            })
+            .unwrap_or(default.tokens);
+
+        let get_list = |keys: &[&str], lowercase: bool| -> Option<Vec<String>> {
+            get_yaml_value(&yaml_value, keys)
+                .and_then(|v| v.as_sequence())
+                .map(|seq| {
+                    seq.iter()
+                        .filter_map(|v| v.as_str())
+                        .map(|s| match lowercase {
+                            true => s.trim().to_lowercase(),
+                            false => s.trim().to_string(),
+                        })
+                        .filter(|s| !s.is_empty())
+                        .collect()
+                })
+        };
+        let data_roots =
+            get_list(&["data_sources", "allowed_roots"], false).unwrap_or(default.data_roots);
+        let data_hosts =
+            get_list(&["data_sources", "allowed_hosts"], true).unwrap_or(default.data_hosts);
+        let data_aliases = get_yaml_value(&yaml_value, &["data_sources", "aliases"])
+            .and_then(|v| v.as_mapping())
+            .map(|mapping| {
+                mapping
+                    .iter()
+                    .filter_map(|(name, source)| {
+                        Some((
+                            name.as_str()?.trim().to_lowercase(),
+                            source.as_str()?.trim().to_string(),
+                        ))
+                    })
+                    .collect()
+            })
+            .unwrap_or(default.data_aliases);
+
+        let profiles = get_yaml_value(&yaml_value, &["profiles"])
+            .and_then(|v| v.as_mapping())
+            .map(|mapping| {
+                mapping
+                    .iter()
+                    .filter_map(|(name, params)| {
+                        let name = name.as_str()?.trim().to_string();
+                        match profile::check_profile_name(&name) {
+                            Ok(()) => Some((name, profile::parse_profile_params(params))),
+                            Err(e) => {
+                                tracing::warn!("{}，已忽略", e);
+                                None
+                            }
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or(default.profiles);
+        let profiles_file = get_str(&["profiles_file"], default.profiles_file);
+        let admin_token = get_str(&["auth", "admin_token"], default.admin_token);
+
+        ServerConfig {
+            template_hosts,
+            template_cache_ttl,
+            template_max_size,
+            profile_name,
+            update_interval,
+            web_page_url,
+            userinfo,
+            user_agents,
+            tokens,
+            data_roots,
+            data_hosts,
+            data_aliases,
+            profiles,
+            profiles_file,
+            admin_token,
+        }
+    }
+
+    /// 检查source参数，返回实际读取的数据源：
+    /// - 别名：返回服务器配置中对应的路径或链接
+    /// - https链接：主机必须在data_sources.allowed_hosts中
+    /// - 本地路径：转换为绝对路径（解析..和符号链接）后，必须在某个allowed_roots目录中
+    pub fn resolve_data_source(&self, source: &str) -> Result<String, AppError> {
+        // 针对win11中"复制文件地址"出现双引号的情况
+        let source = source.trim().trim_matches('"');
+        if let Some((_, target)) = self
+            .data_aliases
+            .iter()
+            .find(|(name, _)| *name == source.to_lowercase())
+        {
+            return Ok(target.clone());
+        }
+
+        if source.contains("://") {
+            if !source.to_lowercase().starts_with("https://") {
+                return Err(AppError::Forbidden("网络数据源只支持https链接".to_string()));
+            }
+            check_allowed_url(source, &self.data_hosts, "data_sources.allowed_hosts")
+                .map_err(AppError::Forbidden)?;
+            return Ok(source.to_string());
+        }
+
+        // 路径不存在跟不在允许的目录中返回同样的提示，提示中也不包含路径，避免借此探测服务器上的文件，
+        // 具体的原因只写入服务器的日志
+        let rejected = |reason: &str| {
+            tracing::warn!("拒绝数据源{}：{}", source, reason);
+            AppError::Forbidden("数据源不存在或者不在允许的数据目录中".to_string())
+        };
+        let path = fs::canonicalize(source).map_err(|e| rejected(&e.to_string()))?;
+        let allowed = self
+            .data_roots
+            .iter()
+            .filter_map(|root| fs::canonicalize(root).ok())
+            .any(|root: PathBuf| path.starts_with(root));
+        match allowed {
+            true => Ok(path.to_string_lossy().to_string()),
+            false => Err(rejected("不在data_sources.allowed_roots的目录中")),
+        }
+    }
+
+    /// 根据User-Agent选择转换目标，没有匹配的规则就返回None
+    pub fn detect_target(&self, user_agent: &str) -> Option<String> {
+        // Quantumult X的User-Agent中，空格可能被编码为%20
+        let user_agent = user_agent.to_lowercase().replace("%20", " ");
+        self.user_agents
+            .iter()
+            .find(|(keyword, _)| user_agent.contains(keyword.as_str()))
+            .map(|(_, target)| target.clone())
+    }
+}
+
+fn default_user_agents() -> Vec<(String, String)> {
+    DEFAULT_USER_AGENTS
+        .iter()
+        .map(|(keyword, target)| (keyword.to_string(), target.to_string()))
+        .collect()
+}
+
+// 解析一条User-Agent映射规则：{ match: <关键字>, target: <转换目标> }
+fn parse_user_agent_rule(rule: &YamlValue) -> Option<(String, String)> {
+    let keyword = get_yaml_value(rule, &["match"])
+        .and_then(|v| v.as_str())
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty());
+    let target = get_yaml_value(rule, &["target"])
+        .and_then(|v| v.as_str())
+        .map(|s| s.trim().to_lowercase());
+    match (keyword, target) {
+        (Some(keyword), Some(target)) if TARGETS.contains(&target.as_str()) => {
+            Some((keyword, target))
+        }
+        _ => {
+            let rule = serde_json::to_string(rule).unwrap_or_default();
+            tracing::warn!("无效的User-Agent映射规则：{}，已忽略", rule);
+            None
+        }
+    }
+}
+
+/// 将{ upload, download, total, expire }转换为subscription-userinfo的格式，
+/// 例如"upload=0; download=1024; total=10737418240; expire=1767196800"，没有设置的字段不输出
+pub fn format_userinfo(userinfo: &YamlValue) -> String {
+    ["upload", "download", "total", "expire"]
+        .iter()
+        .filter_map(|&key| {
+            get_yaml_value(userinfo, &[key])
+                .and_then(|v| v.as_u64())
+                .map(|value| format!("{}={}", key, value))
+        })
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+/// 检查链接是否在允许的主机列表中（config_key为服务器配置中对应的字段，用于错误提示）：
+/// 只写主机名（可以使用*.通配子域名）的只允许https，写完整的http://主机:端口的允许这个地址的http链接
+pub fn check_allowed_url(
+    url: &str,
+    allowed_hosts: &[String],
+    config_key: &str,
+) -> Result<(), String> {
+    let parsed = Url::parse(url).map_err(|e| format!("链接无效（{}）", e))?;
+    let host = parsed.host_str().unwrap_or_default().to_lowercase();
+    if host.is_empty() {
+        return Err("链接中没有主机名".to_string());
+    }
+    let allowed = allowed_hosts
+        .iter()
+        .any(|entry| match entry.contains("://") {
+            true => Url::parse(entry).is_ok_and(|allowed| {
+                allowed.scheme() == parsed.scheme()
+                    && allowed.host_str() == Some(host.as_str())
+                    && allowed.port_or_known_default() == parsed.port_or_known_default()
+            }),
+            false => {
+                parsed.scheme() == "https"
+                    && match entry.strip_prefix("*.") {
+                        Some(domain) => host.ends_with(&format!(".{}", domain)),
+                        None => *entry == host,
+                    }
+            }
+        });
+    match allowed {
+        true => Ok(()),
+        false => Err(format!("{}不在server配置的{}中", host, config_key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_agents_map_to_targets() {
+        let config = ServerConfig::default();
+        let cases = [
+            (
+                "HiddifyNext/2.5.7 (android) like ClashMeta v2ray sing-box",
+                Some("singbox"),
+            ),
+            ("SFI/1.10.7 (iOS 17)", Some("singbox")),
+            ("clash-verge/v1.7.7", Some("clash")),
+            ("ClashMetaForAndroid/2.10.1.Meta", Some("clash")),
+            ("Stash/2.4.7 Clash/1.9.0", Some("clash")),
+            ("Surfboard/2.24.0", Some("surfboard")),
+            ("Surge iOS/3025", Some("surge")),
+            ("Quantumult%20X/1.4.2", Some("quanx")),
+            ("Loon/3.2.1", Some("loon")),
+            ("v2rayNG/1.8.19", Some("v2ray")),
+            ("Xray-core/1.8.24", Some("xray")),
+            ("curl/8.5.0", None),
+        ];
+        for (user_agent, target) in cases {
+            assert_eq!(
+                config.detect_target(user_agent).as_deref(),
+                target,
+                "{}",
+                user_agent
+            );
+        }
+    }
+
+    #[test]
+    fn custom_user_agent_rules_come_first() {
+        let rules: YamlValue = serde_yaml::from_str(
+            "[{ match: ' Stash ', target: Surge }, { match: foo, target: bar }, { target: v2ray }]",
+        )
+        .unwrap();
+        let mut user_agents: Vec<(String, String)> = rules
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .filter_map(parse_user_agent_rule)
+            .collect();
+        assert_eq!(user_agents, [("stash".to_string(), "surge".to_string())]);
+        user_agents.extend(default_user_agents());
+        let config = ServerConfig {
+            user_agents,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.detect_target("Stash/2.4.7 Clash/1.9.0").as_deref(),
+            Some("surge")
+        );
+        assert_eq!(
+            config.detect_target("clash-verge/v1.7.7").as_deref(),
+            Some("clash")
+        );
+    }
+
+    // 临时目录中的数据目录root（含有a.csv）以及它外面的secret.csv
+    fn data_dirs(name: &str) -> (PathBuf, PathBuf) {
+        let base = std::env::temp_dir().join(format!("cfwks-{}-{}", name, std::process::id()));
+        let root = base.join("root");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.csv"), "").unwrap();
+        fs::write(base.join("secret.csv"), "").unwrap();
+        (base, root)
+    }
+
+    #[test]
+    fn local_sources_must_be_in_data_roots() {
+        let (base, root) = data_dirs("roots");
+        let config = ServerConfig {
+            data_roots: vec![root.to_string_lossy().to_string()],
+            ..Default::default()
+        };
+        let inside = root.join("a.csv");
+        let expected = fs::canonicalize(&inside).unwrap();
+        let quoted = format!("\"{}\"", inside.display());
+        assert_eq!(
+            config.resolve_data_source(&quoted).unwrap(),
+            expected.to_string_lossy()
+        );
+        let escaped = root.join("..").join("secret.csv");
+        let missing = root.join("missing.csv");
+        for source in [&escaped, &base.join("secret.csv"), &missing] {
+            let result = config.resolve_data_source(&source.to_string_lossy());
+            // 提示中不包含请求的路径
+            assert!(
+                matches!(&result, Err(AppError::Forbidden(m)) if !m.contains("csv")),
+                "{:?}",
+                source
+            );
+        }
+        #[cfg(unix)]
+        {
+            let link = root.join("link.csv");
+            let _ = fs::remove_file(&link);
+            std::os::unix::fs::symlink(base.join("secret.csv"), &link).unwrap();
+            let result = config.resolve_data_source(&link.to_string_lossy());
+            assert!(matches!(result, Err(AppError::Forbidden(_))));
+        }
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn remote_sources_and_aliases() {
+        let config = ServerConfig {
+            data_hosts: vec!["data.example.com".to_string()],
+            data_aliases: vec![("home".to_string(), "/srv/data/home.csv".to_string())],
+            ..Default::default()
+        };
+        assert_eq!(
+            config.resolve_data_source(" HOME ").unwrap(),
+            "/srv/data/home.csv"
+        );
+        let url = "https://data.example.com/a.csv?token=abc";
+        assert_eq!(config.resolve_data_source(url).unwrap(), url);
+        for source in [
+            "http://data.example.com/a.csv",
+            "https://other.example.com/a.csv",
+            "file:///etc/passwd",
+        ] {
+            let result = config.resolve_data_source(source);
+            assert!(matches!(result, Err(AppError::Forbidden(_))), "{}", source);
+        }
+    }
+
+    #[test]
+    fn allowed_urls_match_host_rules() {
+        let hosts = [
+            "example.com".to_string(),
+            "*.cdn.example.com".to_string(),
+            "http://127.0.0.1:8080".to_string(),
+        ];
+        let check = |url: &str| check_allowed_url(url, &hosts, "test").is_ok();
+        assert!(check("https://example.com/a"));
+        assert!(check("https://a.cdn.example.com/a"));
+        assert!(check("http://127.0.0.1:8080/a"));
+        assert!(!check("http://example.com/a"));
+        assert!(!check("https://cdn.example.com/a"));
+        assert!(!check("https://evilexample.com/a"));
+        assert!(!check("http://127.0.0.1:8081/a"));
+        assert!(!check("not a url"));
+    }
+}
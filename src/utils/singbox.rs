@@ -1,4 +1,4 @@
-use super::config::{get_yaml_value, get_yaml_value_with_fallback};
+use super::{config::get_yaml_value, error::AppError, tls::TlsOptions};
 use serde_json::{json, Value as JsonValue};
 use serde_yaml::Value as YamlValue;
 use std::collections::HashMap;
@@ -26,10 +26,15 @@ pub fn build_singbox_config_json(
                 build_ss_singbox_config(yaml_value, remarks, server_address, server_port);
             return (remarks_name, ss_singbox);
         }
+        "vmess" => {
+            let (remarks_name, vmess_singbox) =
+                build_vmess_singbox_config(yaml_value, remarks, server_address, server_port);
+            return (remarks_name, vmess_singbox);
+        }
         _ => {}
     }
 
-    return (String::new(), String::new());
+    (String::new(), String::new())
 }
 
 fn build_ss_singbox_config(
@@ -38,22 +43,20 @@ fn build_ss_singbox_config(
     server_address: String,
     server_port: u16,
 ) -> (String, String) {
-    let path = get_yaml_value(&yaml_value, &["plugin-opts", "path"])
+    let path = get_yaml_value(yaml_value, &["plugin-opts", "path"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let host = get_yaml_value(&yaml_value, &["plugin-opts", "host"])
+    let host = get_yaml_value(yaml_value, &["plugin-opts", "host"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let password = get_yaml_value(&yaml_value, &["password"])
+    let password = get_yaml_value(yaml_value, &["password"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let tls_val = get_yaml_value(&yaml_value, &["plugin-opts", "tls"])
-        .and_then(|v| v.as_bool())
-        .unwrap_or(true);
+    let tls = TlsOptions::from_yaml(yaml_value, "ss");
 
-    let insert_tls_str = match tls_val {
-        true => format!("tls;"),
-        false => format!(""),
+    let insert_tls_str = match tls.enabled {
+        true => "tls;".to_string(),
+        false => String::new(),
     };
     let plugin_value = format!(
         "{}mux=0;mode=websocket;path={};host={}",
@@ -81,7 +84,7 @@ fn build_ss_singbox_config(
 
     let json_string = serde_json::to_string_pretty(&ss_jsonvalue).unwrap_or_default();
 
-    return (remarks, json_string);
+    (remarks, json_string)
 }
 
 fn build_vless_singbox_config(
@@ -90,21 +93,17 @@ fn build_vless_singbox_config(
     server_address: String,
     server_port: u16,
 ) -> (String, String) {
-    let uuid = get_yaml_value(&yaml_value, &["uuid"])
-        .and_then(|v| v.as_str())
-        .unwrap_or_default();
-    let client_fingerprint = get_yaml_value(&yaml_value, &["client-fingerprint"])
+    let uuid = get_yaml_value(yaml_value, &["uuid"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let path = get_yaml_value(&yaml_value, &["ws-opts", "path"])
+    let path = get_yaml_value(yaml_value, &["ws-opts", "path"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let host = get_yaml_value(&yaml_value, &["ws-opts", "headers", "Host"])
+    let host = get_yaml_value(yaml_value, &["ws-opts", "headers", "Host"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
 
-    let tls_server_name =
-        get_yaml_value_with_fallback(&yaml_value, &["servername", "sni"]).unwrap_or_default();
+    let tls = TlsOptions::from_yaml(yaml_value, "vless");
 
     let vless_singbox_config = r#"{
         "type": "vless",
@@ -144,13 +143,12 @@ fn build_vless_singbox_config(
         outer_updates,
         host.to_string(),
         path.to_string(),
-        tls_server_name.to_string(),
-        client_fingerprint.to_string(),
+        &tls,
     );
 
     let json_string = serde_json::to_string_pretty(&result).unwrap_or_default();
 
-    return (remarks, json_string);
+    (remarks, json_string)
 }
 
 fn build_trojan_singbox_config(
@@ -159,21 +157,17 @@ fn build_trojan_singbox_config(
     server_address: String,
     server_port: u16,
 ) -> (String, String) {
-    let password = get_yaml_value(&yaml_value, &["password"])
+    let password = get_yaml_value(yaml_value, &["password"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let client_fingerprint = get_yaml_value(&yaml_value, &["client-fingerprint"])
+    let path = get_yaml_value(yaml_value, &["ws-opts", "path"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let path = get_yaml_value(&yaml_value, &["ws-opts", "path"])
-        .and_then(|v| v.as_str())
-        .unwrap_or_default();
-    let host = get_yaml_value(&yaml_value, &["ws-opts", "headers", "Host"])
+    let host = get_yaml_value(yaml_value, &["ws-opts", "headers", "Host"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
 
-    let tls_server_name =
-        get_yaml_value_with_fallback(&yaml_value, &["sni", "servername"]).unwrap_or_default();
+    let tls = TlsOptions::from_yaml(yaml_value, "trojan");
 
     let trojan_singbox_config = r#"{
         "type": "trojan",
@@ -213,13 +207,86 @@ fn build_trojan_singbox_config(
         outer_updates,
         host.to_string(),
         path.to_string(),
-        tls_server_name.to_string(),
-        client_fingerprint.to_string(),
+        &tls,
     );
 
     let json_string = serde_json::to_string_pretty(&result).unwrap_or_default();
 
-    return (remarks, json_string);
+    (remarks, json_string)
+}
+
+fn build_vmess_singbox_config(
+    yaml_value: &mut YamlValue,
+    remarks: String,
+    server_address: String,
+    server_port: u16,
+) -> (String, String) {
+    let uuid = get_yaml_value(yaml_value, &["uuid"])
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let alter_id = get_yaml_value(yaml_value, &["alterId"])
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0);
+    let cipher = get_yaml_value(yaml_value, &["cipher"])
+        .and_then(|v| v.as_str())
+        .unwrap_or("auto");
+    let path = get_yaml_value(yaml_value, &["ws-opts", "path"])
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let host = get_yaml_value(yaml_value, &["ws-opts", "headers", "Host"])
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+
+    let tls = TlsOptions::from_yaml(yaml_value, "vmess");
+
+    let vmess_singbox_config = r#"{
+        "type": "vmess",
+        "tag": "vmess_tag",
+        "server": "",
+        "server_port": 443,
+        "uuid": "",
+        "security": "auto",
+        "alter_id": 0,
+        "network": "tcp",
+        "tls": {
+            "enabled": true,
+            "server_name": "",
+            "insecure": true,
+            "utls": {
+                "enabled": true,
+                "fingerprint": "chrome"
+            }
+        },
+        "transport": {
+            "type": "ws",
+            "path": "/",
+            "headers": {"Host": ""},
+            "early_data_header_name": "Sec-WebSocket-Protocol"
+        }
+    }"#;
+
+    let mut jsonvalue: JsonValue = serde_json::from_str(vmess_singbox_config).unwrap_or_default();
+
+    let outer_updates = HashMap::from([
+        ("tag", json!(remarks)),
+        ("server", json!(server_address)),
+        ("server_port", json!(server_port)),
+        ("uuid", json!(uuid)),
+        ("security", json!(cipher)),
+        ("alter_id", json!(alter_id)),
+    ]);
+
+    let result: JsonValue = update_singbox_json_value(
+        &mut jsonvalue,
+        outer_updates,
+        host.to_string(),
+        path.to_string(),
+        &tls,
+    );
+
+    let json_string = serde_json::to_string_pretty(&result).unwrap_or_default();
+
+    (remarks, json_string)
 }
 
 fn update_singbox_json_value(
@@ -227,8 +294,7 @@ fn update_singbox_json_value(
     outer_updates: HashMap<&str, JsonValue>,
     host: String,
     path: String,
-    tls_server_name: String,
-    client_fingerprint: String,
+    tls_options: &TlsOptions,
 ) -> JsonValue {
     // 修改jsonvalue的外层字段（多个字段）
     for (key, new_value) in outer_updates {
@@ -236,20 +302,27 @@ fn update_singbox_json_value(
             *outer_value = new_value;
         }
     }
-    // 修改jsonvalue的tls字段
+    // 修改jsonvalue的tls字段，是否开启tls由节点的tls字段决定（没有设置才根据workers.dev判断）
     if let Some(tls) = jsonvalue.get_mut("tls") {
-        if let Some(server_name) = tls.get_mut("server_name") {
-            *server_name = json!(tls_server_name);
-        }
-        // 手动关闭tls
-        if host.ends_with("workers.dev") {
-            if let Some(tls_enabled) = tls.get_mut("enabled") {
-                *tls_enabled = json!(false);
+        if !tls_options.enabled {
+            *tls = json!({ "enabled": false });
+        } else {
+            tls["enabled"] = json!(true);
+            tls["server_name"] = json!(tls_options.server_name);
+            tls["insecure"] = json!(tls_options.skip_cert_verify);
+            if !tls_options.alpn.is_empty() {
+                tls["alpn"] = json!(tls_options.alpn);
             }
-        }
-        if let Some(utls) = tls.get_mut("utls") {
-            if let Some(fingerprint) = utls.get_mut("fingerprint") {
-                *fingerprint = json!(client_fingerprint);
+            // 没有设置指纹，就保留默认的chrome指纹
+            if !tls_options.fingerprint.is_empty() {
+                tls["utls"]["fingerprint"] = json!(tls_options.fingerprint);
+            }
+            let ech_config = tls_options.ech_pem_lines();
+            if !ech_config.is_empty() {
+                tls["ech"] = json!({ "enabled": true, "config": ech_config });
+            }
+            if tls_options.fragment.is_some() {
+                tls["fragment"] = json!(true);
             }
         }
     }
@@ -267,3 +340,640 @@ fn update_singbox_json_value(
 
     jsonvalue.clone()
 }
+
+/// 解析sing-box的版本号（如"1.11"、"1.12.0"、"v1.10.3"），返回1.x中的x，
+/// 低于1.10的是旧版写法，不需要迁移；高于1.12的按1.12处理
+pub fn parse_singbox_version(text: &str) -> Option<u32> {
+    let mut parts = text.trim().trim_start_matches(['v', 'V']).split('.');
+    let major = parts.next()?.parse::<u32>().ok()?;
+    let minor = parts
+        .next()
+        .and_then(|s| s.parse::<u32>().ok())
+        .unwrap_or(0);
+    match (major, minor) {
+        (1, minor) => Some(minor.min(12)),
+        (2.., _) => Some(12),
+        _ => None,
+    }
+}
+
+/// 从客户端的User-Agent中识别sing-box的版本，例如：
+/// "SFA/1.11.4 (Android 14; sing-box 1.11.4)"、"sing-box 1.12.0"、"SFI/1.10.7"
+pub fn detect_singbox_version(user_agent: &str) -> Option<u32> {
+    let ua = user_agent.to_lowercase();
+    let version = match ua.find("sing-box") {
+        Some(pos) => ua[pos + "sing-box".len()..].trim_start_matches([' ', '/']),
+        None => ["sfa/", "sfi/", "sfm/", "sft/"]
+            .iter()
+            .find_map(|prefix| ua.find(prefix).map(|pos| &ua[pos + prefix.len()..]))?,
+    };
+    let version: String = version
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == 'v')
+        .collect();
+    parse_singbox_version(&version)
+}
+
+/// 将旧版写法的sing-box配置（模板）迁移为指定版本的写法，version为1.x中的x，0就不迁移
+/// - 1.10：tun入站的inet4_address/inet6_address等字段合并为address等数组
+/// - 1.11：入站的sniff、domain_strategy改为sniff、resolve路由规则动作，dns、block特殊出站改为hijack-dns、reject规则动作
+/// - 1.12：DNS服务器改为type + server的新写法，dns.fakeip的地址范围移到fakeip服务器中，
+///   `outbound: any`的DNS规则改为default_domain_resolver
+///
+/// 模板无法迁移（如fakeip服务器没有地址范围）时返回500错误
+pub fn migrate_singbox_config(config: &mut JsonValue, version: u32) -> Result<(), AppError> {
+    if version >= 10 {
+        migrate_tun_address(config);
+    }
+    if version >= 11 {
+        migrate_rule_actions(config);
+    }
+    if version >= 12 {
+        migrate_dns_servers(config)?;
+    }
+    Ok(())
+}
+
+/// 将生成的节点出站迁移为指定版本的写法，version为None（没有传入也识别不到版本）时不迁移，
+/// domain_resolver为解析节点域名使用的DNS服务器
+pub fn migrate_singbox_outbound(
+    outbound: &mut JsonValue,
+    version: Option<u32>,
+    domain_resolver: &str,
+) {
+    let version = match version {
+        Some(version) => version,
+        None => return,
+    };
+    // TLS分片从1.12开始支持，确定是旧版本的客户端不认识这个字段
+    if version < 12 {
+        if let Some(tls) = outbound.get_mut("tls").and_then(JsonValue::as_object_mut) {
+            tls.remove("fragment");
+        }
+    }
+    // 1.12开始，服务器地址为域名的出站使用domain_resolver解析
+    if version >= 12 && !domain_resolver.is_empty() {
+        let server = outbound["server"].as_str().unwrap_or_default();
+        let is_domain = !server.is_empty()
+            && server
+                .trim_matches(['[', ']'])
+                .parse::<std::net::IpAddr>()
+                .is_err();
+        if is_domain {
+            outbound["domain_resolver"] = json!(domain_resolver);
+        }
+    }
+}
+
+// 1.10：inet4_address、inet6_address -> address，inet4_route_address等同理
+fn migrate_tun_address(config: &mut JsonValue) {
+    let renames = [
+        (["inet4_address", "inet6_address"], "address"),
+        (
+            ["inet4_route_address", "inet6_route_address"],
+            "route_address",
+        ),
+        (
+            ["inet4_route_exclude_address", "inet6_route_exclude_address"],
+            "route_exclude_address",
+        ),
+    ];
+    let inbounds = match config.get_mut("inbounds").and_then(JsonValue::as_array_mut) {
+        Some(inbounds) => inbounds,
+        None => return,
+    };
+    for inbound in inbounds.iter_mut().filter(|x| x["type"] == "tun") {
+        let inbound = match inbound.as_object_mut() {
+            Some(inbound) => inbound,
+            None => continue,
+        };
+        for (old_keys, new_key) in renames {
+            let mut addresses: Vec<JsonValue> = Vec::new();
+            for old_key in old_keys {
+                match inbound.remove(old_key) {
+                    Some(JsonValue::Array(items)) => addresses.extend(items),
+                    Some(JsonValue::String(item)) => addresses.push(json!(item)),
+                    _ => {}
+                }
+            }
+            if !addresses.is_empty() {
+                inbound.insert(new_key.to_string(), JsonValue::Array(addresses));
+            }
+        }
+    }
+}
+
+// 1.11：sniff -> {"action": "sniff"}，domain_strategy -> {"action": "resolve"}，
+// dns出站 -> hijack-dns，block出站 -> reject
+fn migrate_rule_actions(config: &mut JsonValue) {
+    let mut sniff = false;
+    // 入站的domain_strategy：(strategy, 使用它的入站tag)，有入站没有tag时不限制入站
+    let mut resolves: Vec<(String, Option<Vec<String>>)> = Vec::new();
+    if let Some(inbounds) = config.get_mut("inbounds").and_then(JsonValue::as_array_mut) {
+        for inbound in inbounds.iter_mut().filter_map(JsonValue::as_object_mut) {
+            sniff |= inbound.remove("sniff").and_then(|v| v.as_bool()) == Some(true);
+            inbound.remove("sniff_override_destination");
+            let strategy = match inbound.remove("domain_strategy") {
+                Some(JsonValue::String(strategy)) if !strategy.is_empty() => strategy,
+                _ => continue,
+            };
+            let tag = inbound
+                .get("tag")
+                .and_then(|v| v.as_str())
+                .map(String::from);
+            match resolves.iter_mut().find(|(s, _)| *s == strategy) {
+                Some((_, tags)) => match (tags.as_mut(), tag) {
+                    (Some(tags), Some(tag)) => tags.push(tag),
+                    _ => *tags = None,
+                },
+                None => resolves.push((strategy, tag.map(|tag| vec![tag]))),
+            }
+        }
+    }
+
+    // 找出特殊出站的tag，并从outbounds中删除
+    let mut actions: HashMap<String, &str> = HashMap::new();
+    if let Some(outbounds) = config
+        .get_mut("outbounds")
+        .and_then(JsonValue::as_array_mut)
+    {
+        outbounds.retain(|outbound| {
+            let tag = outbound["tag"].as_str().unwrap_or_default().to_string();
+            match outbound["type"].as_str() {
+                Some("dns") => {
+                    actions.insert(tag, "hijack-dns");
+                    false
+                }
+                Some("block") => {
+                    actions.insert(tag, "reject");
+                    false
+                }
+                _ => true,
+            }
+        });
+        // 代理组中也不能再引用它们（用get_mut，避免给不是代理组的出站加上"outbounds": null）
+        for outbound in outbounds.iter_mut() {
+            if let Some(members) = outbound
+                .get_mut("outbounds")
+                .and_then(JsonValue::as_array_mut)
+            {
+                members.retain(|x| !actions.contains_key(x.as_str().unwrap_or_default()));
+            }
+        }
+    }
+
+    if let Some(route) = config.get_mut("route").and_then(JsonValue::as_object_mut) {
+        if let Some(rules) = route.get_mut("rules").and_then(JsonValue::as_array_mut) {
+            for rule in rules.iter_mut().filter_map(JsonValue::as_object_mut) {
+                let action = rule
+                    .get("outbound")
+                    .and_then(|v| v.as_str())
+                    .and_then(|tag| actions.get(tag).copied());
+                if let Some(action) = action {
+                    rule.remove("outbound");
+                    rule.insert("action".to_string(), json!(action));
+                }
+            }
+        }
+        if let Some(final_tag) = route.get("final").and_then(|v| v.as_str()) {
+            if actions.contains_key(final_tag) {
+                route.remove("final");
+            }
+        }
+    }
+
+    // 嗅探和解析的规则动作放在路由规则的最前面（先嗅探出域名再解析）
+    let mut leading_rules = Vec::new();
+    if sniff {
+        leading_rules.push(json!({ "action": "sniff" }));
+    }
+    for (strategy, tags) in resolves {
+        let mut rule = json!({ "action": "resolve", "strategy": strategy });
+        if let Some(tags) = tags {
+            rule["inbound"] = json!(tags);
+        }
+        leading_rules.push(rule);
+    }
+    if leading_rules.is_empty() {
+        return;
+    }
+    let rules = config
+        .as_object_mut()
+        .map(|config| config.entry("route").or_insert(json!({})))
+        .and_then(JsonValue::as_object_mut)
+        .map(|route| route.entry("rules").or_insert(json!([])))
+        .and_then(JsonValue::as_array_mut);
+    if let Some(rules) = rules {
+        rules.splice(0..0, leading_rules);
+    }
+}
+
+// 1.12：DNS服务器的新写法，例如 "tls://8.8.8.8" -> {"type": "tls", "server": "8.8.8.8"}
+fn migrate_dns_servers(config: &mut JsonValue) -> Result<(), AppError> {
+    // 直连出站的tag，新版本不允许DNS服务器使用空的直连出站作为detour
+    let direct_tags: Vec<String> = config["outbounds"]
+        .as_array()
+        .map(|outbounds| {
+            outbounds
+                .iter()
+                .filter(|x| x["type"] == "direct")
+                .filter_map(|x| x["tag"].as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let dns = match config.get_mut("dns").and_then(JsonValue::as_object_mut) {
+        Some(dns) => dns,
+        None => return Ok(()),
+    };
+    // dns.fakeip中的地址范围移到fakeip服务器中
+    let fakeip_ranges: serde_json::Map<String, JsonValue> = dns
+        .remove("fakeip")
+        .and_then(|fakeip| fakeip.as_object().cloned())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(key, _)| key == "inet4_range" || key == "inet6_range")
+        .collect();
+
+    // rcode://服务器被删除，引用它们的DNS规则改为predefined动作
+    let mut rcode_servers: HashMap<String, &str> = HashMap::new();
+    if let Some(servers) = dns.get_mut("servers").and_then(JsonValue::as_array_mut) {
+        servers.retain(|server| {
+            let address = server["address"].as_str().unwrap_or_default();
+            match address.strip_prefix("rcode://") {
+                Some(rcode) => {
+                    let rcode = match rcode {
+                        "refused" => "REFUSED",
+                        "name_error" => "NXDOMAIN",
+                        "server_failure" => "SERVFAIL",
+                        _ => "NOERROR",
+                    };
+                    let tag = server["tag"].as_str().unwrap_or_default().to_string();
+                    rcode_servers.insert(tag, rcode);
+                    false
+                }
+                None => true,
+            }
+        });
+        for server in servers.iter_mut() {
+            migrate_dns_server(server, &direct_tags);
+            if server["type"] == "fakeip" {
+                if fakeip_ranges.is_empty() {
+                    return Err(AppError::Internal(
+                        "sing-box模板中有fakeip服务器，但是dns.fakeip中没有inet4_range、inet6_range，无法迁移到1.12"
+                            .to_string(),
+                    ));
+                }
+                if let Some(server) = server.as_object_mut() {
+                    server.extend(fakeip_ranges.clone());
+                }
+            }
+        }
+    }
+
+    let mut default_resolver = None;
+    if let Some(rules) = dns.get_mut("rules").and_then(JsonValue::as_array_mut) {
+        rules.retain(|rule| match rule["outbound"].as_str() {
+            Some("any") => {
+                default_resolver = rule["server"].as_str().map(String::from);
+                false
+            }
+            _ => true,
+        });
+        for rule in rules.iter_mut().filter_map(JsonValue::as_object_mut) {
+            let rcode = rule
+                .get("server")
+                .and_then(|v| v.as_str())
+                .and_then(|tag| rcode_servers.get(tag).copied());
+            if let Some(rcode) = rcode {
+                rule.remove("server");
+                rule.insert("action".to_string(), json!("predefined"));
+                rule.insert("rcode".to_string(), json!(rcode));
+            }
+        }
+    }
+
+    if let Some(resolver) = default_resolver {
+        if let Some(route) = config.get_mut("route").and_then(JsonValue::as_object_mut) {
+            route
+                .entry("default_domain_resolver")
+                .or_insert(json!(resolver));
+        }
+    }
+    Ok(())
+}
+
+// 单个DNS服务器：address -> type + server（+ server_port、path），address_resolver -> domain_resolver
+fn migrate_dns_server(server: &mut JsonValue, direct_tags: &[String]) {
+    let server = match server.as_object_mut() {
+        Some(server) => server,
+        None => return,
+    };
+    let address = match server.remove("address") {
+        Some(JsonValue::String(address)) => address,
+        Some(other) => {
+            server.insert("address".to_string(), other);
+            return;
+        }
+        None => return,
+    };
+
+    let (server_type, rest) = match address.split_once("://") {
+        Some((scheme, rest)) => (scheme.to_string(), rest.to_string()),
+        None => match address.as_str() {
+            "local" | "fakeip" | "dhcp" => (address.clone(), String::new()),
+            _ => ("udp".to_string(), address.clone()),
+        },
+    };
+    let server_type = match server_type.as_str() {
+        "tls" | "https" | "h3" | "quic" | "tcp" | "udp" | "dhcp" | "local" | "fakeip" => {
+            server_type
+        }
+        _ => "udp".to_string(),
+    };
+    server.insert("type".to_string(), json!(server_type));
+
+    if !rest.is_empty() && server_type != "dhcp" {
+        let (host_port, path) = match rest.split_once('/') {
+            Some((host_port, path)) => (host_port.to_string(), format!("/{}", path)),
+            None => (rest.clone(), String::new()),
+        };
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) if !host.ends_with(':') && port.parse::<u16>().is_ok() => {
+                (host.to_string(), port.parse::<u16>().ok())
+            }
+            _ => (host_port.clone(), None),
+        };
+        server.insert("server".to_string(), json!(host.trim_matches(['[', ']'])));
+        if let Some(port) = port {
+            server.insert("server_port".to_string(), json!(port));
+        }
+        if ["https", "h3"].contains(&server_type.as_str())
+            && !path.is_empty()
+            && path != "/dns-query"
+        {
+            server.insert("path".to_string(), json!(path));
+        }
+    }
+
+    if let Some(resolver) = server.remove("address_resolver") {
+        server.insert("domain_resolver".to_string(), resolver);
+    }
+    server.remove("address_strategy");
+    server.remove("strategy");
+    let direct_detour = server
+        .get("detour")
+        .and_then(|v| v.as_str())
+        .is_some_and(|detour| direct_tags.iter().any(|tag| tag == detour));
+    if direct_detour {
+        server.remove("detour");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy_config() -> JsonValue {
+        json!({
+            "inbounds": [{ "type": "mixed", "tag": "mixed-in", "sniff": true }],
+            "outbounds": [
+                { "type": "selector", "tag": "proxy", "outbounds": ["auto", "direct", "block"] },
+                { "type": "direct", "tag": "direct" },
+                { "type": "block", "tag": "block" },
+                { "type": "dns", "tag": "dns-out" }
+            ],
+            "route": {
+                "rules": [
+                    { "protocol": "dns", "outbound": "dns-out" },
+                    { "geosite": "ads", "outbound": "block" }
+                ],
+                "final": "proxy"
+            }
+        })
+    }
+
+    #[test]
+    fn versions_are_parsed() {
+        assert_eq!(parse_singbox_version("1.9.7"), Some(9));
+        assert_eq!(parse_singbox_version("1.10"), Some(10));
+        assert_eq!(parse_singbox_version(" v1.11.4 "), Some(11));
+        assert_eq!(parse_singbox_version("1.13.0-alpha"), Some(12));
+        assert_eq!(parse_singbox_version("2.0"), Some(12));
+        assert_eq!(parse_singbox_version("0.9"), None);
+        assert_eq!(parse_singbox_version("latest"), None);
+    }
+
+    #[test]
+    fn versions_are_detected_from_user_agent() {
+        let cases = [
+            ("SFA/1.11.4 (Android 14; sing-box 1.11.4)", Some(11)),
+            ("sing-box 1.12.0", Some(12)),
+            ("sing-box/1.10.1", Some(10)),
+            ("SFI/1.10.7 (iOS 17)", Some(10)),
+            ("ClashMetaForAndroid/2.10.1", None),
+        ];
+        for (user_agent, version) in cases {
+            assert_eq!(
+                detect_singbox_version(user_agent),
+                version,
+                "{}",
+                user_agent
+            );
+        }
+    }
+
+    #[test]
+    fn tun_addresses_are_merged_from_1_10() {
+        let tun = json!({
+            "inbounds": [{
+                "type": "tun",
+                "inet4_address": "172.19.0.1/30",
+                "inet6_address": ["fdfe:dcba:9876::1/126"],
+                "inet4_route_address": ["0.0.0.0/1"]
+            }]
+        });
+        let mut config = tun.clone();
+        migrate_singbox_config(&mut config, 0).unwrap();
+        assert_eq!(config, tun);
+        migrate_singbox_config(&mut config, 10).unwrap();
+        assert_eq!(
+            config["inbounds"][0],
+            json!({
+                "type": "tun",
+                "address": ["172.19.0.1/30", "fdfe:dcba:9876::1/126"],
+                "route_address": ["0.0.0.0/1"]
+            })
+        );
+    }
+
+    #[test]
+    fn dns_servers_are_migrated_from_1_12() {
+        let mut config = json!({
+            "outbounds": [{ "type": "direct", "tag": "direct" }],
+            "dns": {
+                "servers": [
+                    { "tag": "google", "address": "tls://8.8.8.8", "address_resolver": "local" },
+                    { "tag": "ali", "address": "https://223.5.5.5/dns-query", "detour": "direct" },
+                    { "tag": "local", "address": "local" },
+                    { "tag": "block", "address": "rcode://success" }
+                ],
+                "rules": [
+                    { "outbound": "any", "server": "local" },
+                    { "geosite": "ads", "server": "block" }
+                ]
+            },
+            "route": {}
+        });
+        migrate_singbox_config(&mut config, 12).unwrap();
+        assert_eq!(
+            config["dns"]["servers"],
+            json!([
+                { "tag": "google", "type": "tls", "server": "8.8.8.8", "domain_resolver": "local" },
+                { "tag": "ali", "type": "https", "server": "223.5.5.5" },
+                { "tag": "local", "type": "local" }
+            ])
+        );
+        assert_eq!(
+            config["dns"]["rules"],
+            json!([{ "geosite": "ads", "action": "predefined", "rcode": "NOERROR" }])
+        );
+        assert_eq!(config["route"]["default_domain_resolver"], "local");
+    }
+
+    #[test]
+    fn domain_servers_use_domain_resolver() {
+        let mut outbound = json!({ "type": "vless", "server": "a.example.com" });
+        migrate_singbox_outbound(&mut outbound, Some(12), "local");
+        assert_eq!(outbound["domain_resolver"], "local");
+        for server in ["1.1.1.1", "[2606:4700::1]"] {
+            let mut outbound = json!({ "type": "vless", "server": server });
+            migrate_singbox_outbound(&mut outbound, Some(12), "local");
+            assert!(outbound.get("domain_resolver").is_none(), "{}", server);
+        }
+        let mut outbound = json!({ "type": "vless", "server": "a.example.com" });
+        migrate_singbox_outbound(&mut outbound, Some(11), "local");
+        assert!(outbound.get("domain_resolver").is_none());
+    }
+
+    #[test]
+    fn tls_fragment_is_only_removed_for_older_versions() {
+        let outbound = json!({ "type": "vless", "tls": { "enabled": true, "fragment": true } });
+        // 没有传入也识别不到版本时，保留节点要求的分片
+        for (version, kept) in [
+            (None, true),
+            (Some(9), false),
+            (Some(11), false),
+            (Some(12), true),
+        ] {
+            let mut migrated = outbound.clone();
+            migrate_singbox_outbound(&mut migrated, version, "");
+            assert_eq!(
+                migrated["tls"].get("fragment").is_some(),
+                kept,
+                "{:?}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn rule_actions_keep_non_group_outbounds_clean() {
+        for version in [11, 12] {
+            let mut config = legacy_config();
+            migrate_singbox_config(&mut config, version).unwrap();
+            let outbounds = config["outbounds"].as_array().unwrap();
+            assert_eq!(outbounds.len(), 2);
+            for outbound in outbounds.iter().filter(|x| x["type"] != "selector") {
+                assert!(outbound.get("outbounds").is_none(), "{}", outbound);
+            }
+            assert_eq!(outbounds[0]["outbounds"], json!(["auto", "direct"]));
+            assert_eq!(config["route"]["rules"][0], json!({ "action": "sniff" }));
+            assert_eq!(config["route"]["rules"][1]["action"], "hijack-dns");
+            assert_eq!(config["route"]["rules"][2]["action"], "reject");
+        }
+    }
+
+    #[test]
+    fn domain_strategy_becomes_resolve_action_from_1_11() {
+        let mut config = json!({
+            "inbounds": [
+                { "type": "tun", "tag": "tun-in", "sniff": true, "domain_strategy": "ipv4_only" },
+                { "type": "mixed", "tag": "mixed-in", "domain_strategy": "prefer_ipv6" },
+                { "type": "socks", "tag": "socks-in", "domain_strategy": "ipv4_only" },
+                { "type": "http", "tag": "http-in" }
+            ],
+            "route": { "rules": [{ "geosite": "cn", "outbound": "direct" }] }
+        });
+        migrate_singbox_config(&mut config, 11).unwrap();
+        for inbound in config["inbounds"].as_array().unwrap() {
+            assert!(inbound.get("domain_strategy").is_none(), "{}", inbound);
+        }
+        assert_eq!(
+            config["route"]["rules"],
+            json!([
+                { "action": "sniff" },
+                { "action": "resolve", "strategy": "ipv4_only", "inbound": ["tun-in", "socks-in"] },
+                { "action": "resolve", "strategy": "prefer_ipv6", "inbound": ["mixed-in"] },
+                { "geosite": "cn", "outbound": "direct" }
+            ])
+        );
+
+        // 没有tag的入站不能用inbound匹配，解析所有入站的连接；模板没有路由规则时添加
+        let mut config =
+            json!({ "inbounds": [{ "type": "mixed", "domain_strategy": "prefer_ipv4" }] });
+        migrate_singbox_config(&mut config, 11).unwrap();
+        assert_eq!(
+            config["route"],
+            json!({ "rules": [{ "action": "resolve", "strategy": "prefer_ipv4" }] })
+        );
+    }
+
+    #[test]
+    fn fakeip_servers_get_ranges_from_1_12() {
+        let legacy = json!({
+            "dns": {
+                "servers": [
+                    { "tag": "remote", "address": "tls://8.8.8.8" },
+                    { "tag": "fakeip", "address": "fakeip" }
+                ],
+                "rules": [{ "query_type": ["A", "AAAA"], "server": "fakeip" }],
+                "fakeip": { "enabled": true, "inet4_range": "198.18.0.0/15", "inet6_range": "fc00::/18" }
+            }
+        });
+        let mut config = legacy.clone();
+        migrate_singbox_config(&mut config, 12).unwrap();
+        assert_eq!(
+            config["dns"],
+            json!({
+                "servers": [
+                    { "tag": "remote", "type": "tls", "server": "8.8.8.8" },
+                    { "tag": "fakeip", "type": "fakeip", "inet4_range": "198.18.0.0/15", "inet6_range": "fc00::/18" }
+                ],
+                "rules": [{ "query_type": ["A", "AAAA"], "server": "fakeip" }]
+            })
+        );
+
+        // 1.11不迁移
+        let mut config = legacy.clone();
+        migrate_singbox_config(&mut config, 11).unwrap();
+        assert_eq!(config["dns"], legacy["dns"]);
+
+        // fakeip服务器没有地址范围时，不能生成无效的配置
+        let mut config = legacy;
+        config["dns"].as_object_mut().unwrap().remove("fakeip");
+        let result = migrate_singbox_config(&mut config, 12);
+        assert!(matches!(result, Err(AppError::Internal(m)) if m.contains("fakeip")));
+    }
+
+    #[test]
+    fn migration_without_inbounds_adds_nothing() {
+        let mut config = json!({ "outbounds": [{ "type": "direct", "tag": "direct" }] });
+        migrate_singbox_config(&mut config, 12).unwrap();
+        assert!(config.get("inbounds").is_none());
+        assert_eq!(
+            config["outbounds"],
+            json!([{ "type": "direct", "tag": "direct" }])
+        );
+    }
+}
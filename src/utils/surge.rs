@@ -0,0 +1,173 @@
+use super::{config::get_yaml_value, tls::TlsOptions};
+use serde_yaml::Value as YamlValue;
+
+/// 检查节点能否转换为Surge/Surfboard的代理，不能转换就返回原因
+pub fn check_surge_node(node_type: &str, yaml_value: &YamlValue) -> Result<(), String> {
+    match node_type {
+        "trojan" => match TlsOptions::from_yaml(yaml_value, node_type).enabled {
+            true => Ok(()),
+            false => Err("不支持非TLS的trojan".to_string()),
+        },
+        "vmess" => Ok(()),
+        "ss" => {
+            let plugin = get_yaml_value(yaml_value, &["plugin"])
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+            match plugin {
+                "" | "obfs" => Ok(()),
+                _ => Err(format!("不支持ss的{}插件", plugin)),
+            }
+        }
+        _ => Err(format!("不支持{}协议", node_type)),
+    }
+}
+
+/// 构建Surge/Surfboard的[Proxy]代理行，返回的前面是节点名称，后面是代理行
+pub fn build_surge_proxy(
+    proxy_type: &str,
+    yaml_value: &mut YamlValue,
+    remarks: String,
+    server_address: String,
+    server_port: u16,
+) -> (String, String) {
+    if check_surge_node(proxy_type, yaml_value).is_err() {
+        return (String::new(), String::new());
+    }
+    // 节点名称中的逗号、等号会破坏配置文件的格式
+    let remarks = remarks.replace([',', '='], " ");
+    // Surge/Surfboard的IPv6地址不需要方括号
+    let server = server_address.trim_start_matches('[').trim_end_matches(']');
+
+    let get_str = |keys: &[&str]| -> String {
+        get_yaml_value(yaml_value, keys)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    let mut fields: Vec<String> = Vec::new();
+    match proxy_type {
+        "trojan" => {
+            fields.push(format!("password={}", get_str(&["password"])));
+        }
+        "vmess" => {
+            fields.push(format!("username={}", get_str(&["uuid"])));
+            let alter_id = get_yaml_value(yaml_value, &["alterId"])
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0);
+            if alter_id == 0 {
+                fields.push("vmess-aead=true".to_string());
+            }
+        }
+        "ss" => {
+            fields.push(format!("encrypt-method={}", get_str(&["cipher"])));
+            fields.push(format!("password={}", get_str(&["password"])));
+            let obfs_mode = get_str(&["plugin-opts", "mode"]);
+            if !obfs_mode.is_empty() {
+                fields.push(format!("obfs={}", obfs_mode));
+                fields.push(format!("obfs-host={}", get_str(&["plugin-opts", "host"])));
+            }
+        }
+        _ => {}
+    }
+
+    // websocket传输
+    if get_str(&["network"]) == "ws" {
+        fields.push("ws=true".to_string());
+        let path = get_str(&["ws-opts", "path"]);
+        if !path.is_empty() {
+            fields.push(format!("ws-path={}", path));
+        }
+        let host = get_str(&["ws-opts", "headers", "Host"]);
+        if !host.is_empty() {
+            fields.push(format!("ws-headers=Host:{}", host));
+        }
+    }
+
+    // TLS设置（ss的obfs插件自带tls，不在这里处理）
+    if proxy_type != "ss" {
+        let tls = TlsOptions::from_yaml(yaml_value, proxy_type);
+        if tls.enabled {
+            if proxy_type == "vmess" {
+                fields.push("tls=true".to_string());
+            }
+            if !tls.server_name.is_empty() {
+                fields.push(format!("sni={}", tls.server_name));
+            }
+            fields.push(format!("skip-cert-verify={}", tls.skip_cert_verify));
+        }
+    }
+
+    let surge_proxy = format!(
+        "{} = {}, {}, {}, {}",
+        remarks,
+        proxy_type,
+        server,
+        server_port,
+        fields.join(", ")
+    );
+
+    (remarks, surge_proxy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(yaml: &str) -> YamlValue {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn unsupported_nodes_are_skipped() {
+        assert!(check_surge_node("vless", &node("{}")).is_err());
+        assert!(check_surge_node("ss", &node("plugin: v2ray-plugin")).is_err());
+        let trojan = node("ws-opts: { headers: { Host: a.workers.dev } }");
+        assert!(check_surge_node("trojan", &trojan).is_err());
+        let (name, line) = build_surge_proxy(
+            "vless",
+            &mut node("{}"),
+            "n".to_string(),
+            "1.1.1.1".to_string(),
+            443,
+        );
+        assert!(name.is_empty() && line.is_empty());
+    }
+
+    #[test]
+    fn vmess_ws_tls_proxy_line() {
+        let mut vmess = node(
+            "{ uuid: abc, alterId: 0, network: ws, sni: a.example.com, skip-cert-verify: true, \
+             ws-opts: { path: /ws, headers: { Host: a.example.com } } }",
+        );
+        let (name, line) = build_surge_proxy(
+            "vmess",
+            &mut vmess,
+            "US, LAX=1".to_string(),
+            "[2606:4700::1]".to_string(),
+            443,
+        );
+        assert_eq!(name, "US  LAX 1");
+        assert_eq!(
+            line,
+            "US  LAX 1 = vmess, 2606:4700::1, 443, username=abc, vmess-aead=true, ws=true, \
+             ws-path=/ws, ws-headers=Host:a.example.com, tls=true, sni=a.example.com, \
+             skip-cert-verify=true"
+        );
+    }
+
+    #[test]
+    fn ss_obfs_proxy_line() {
+        let mut ss = node(
+            "{ cipher: aes-128-gcm, password: pw, plugin: obfs, \
+             plugin-opts: { mode: tls, host: a.example.com } }",
+        );
+        let (_, line) =
+            build_surge_proxy("ss", &mut ss, "ss".to_string(), "1.1.1.1".to_string(), 8443);
+        assert_eq!(
+            line,
+            "ss = ss, 1.1.1.1, 8443, encrypt-method=aes-128-gcm, password=pw, obfs=tls, \
+             obfs-host=a.example.com"
+        );
+    }
+}
@@ -0,0 +1,687 @@
+use super::{
+    access_log,
+    error::AppError,
+    metrics, overrides,
+    server_config::{check_allowed_url, ServerConfig},
+};
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// 模板目录，`template/<转换目标>/<模板名称>.<扩展名>`为可选的命名模板
+pub const TEMPLATE_DIR: &str = "template";
+
+lazy_static! {
+    // 远程模板的缓存：链接 -> (获取的时间, 模板内容)
+    static ref REMOTE_TEMPLATE_CACHE: Mutex<HashMap<String, (Instant, String)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// template=false&minimal=true时使用的最小clash配置：混合端口、选择组、自动测速组和MATCH规则
+const MINIMAL_CLASH_TEMPLATE: &str = r#"mixed-port: 7890
+allow-lan: false
+mode: rule
+log-level: info
+proxies: []
+proxy-groups:
+  - name: 🔰 节点选择
+    type: select
+    proxies:
+      - ♻️ 自动选择
+      - "{all}"
+      - DIRECT
+  - name: ♻️ 自动选择
+    type: url-test
+    url: http://www.gstatic.com/generate_204
+    interval: 300
+    tolerance: 50
+    proxies:
+      - "{all}"
+rules:
+  - MATCH,🔰 节点选择
+"#;
+
+/// template=false&minimal=true时使用的最小sing-box配置（旧版写法，跟模板一样按sbver迁移）：
+/// 混合入站、选择组、自动测速组、直连出站和route.final
+const MINIMAL_SINGBOX_TEMPLATE: &str = r#"{
+  "log": { "level": "info" },
+  "inbounds": [
+    { "type": "mixed", "tag": "mixed-in", "listen": "127.0.0.1", "listen_port": 2080 }
+  ],
+  "outbounds": [
+    { "tag": "proxy", "type": "selector", "outbounds": ["auto", "{all}", "direct"] },
+    {
+      "tag": "auto",
+      "type": "urltest",
+      "outbounds": ["{all}"],
+      "url": "http://www.gstatic.com/generate_204",
+      "interval": "10m",
+      "tolerance": 50
+    },
+    { "tag": "direct", "type": "direct" }
+  ],
+  "route": { "final": "proxy" }
+}"#;
+
+/// 最小可运行配置的模板，只支持clash、singbox，其它转换目标返回空字符串
+pub fn minimal_template(target: &str) -> &'static str {
+    match target {
+        "clash" => MINIMAL_CLASH_TEMPLATE,
+        "singbox" => MINIMAL_SINGBOX_TEMPLATE,
+        _ => "",
+    }
+}
+
+/// 读取到的模板：模板内容，以及叠加模板中的覆盖设置（先于订阅链接中的覆盖参数应用）
+#[derive(Default)]
+pub struct Template {
+    pub content: String,
+    pub overrides: Vec<(String, String)>,
+}
+
+/// 模板目录中的一个模板
+pub struct TemplateInfo {
+    pub target: String,
+    pub name: String,
+    pub file: String,
+}
+
+/// 列出所有可用的模板，每个转换目标的默认模板名称为default
+pub fn list_templates(defaults: &HashMap<&str, &str>) -> Vec<TemplateInfo> {
+    let mut targets: Vec<&str> = defaults.keys().copied().collect();
+    targets.sort();
+
+    let mut templates = Vec::new();
+    for target in targets {
+        templates.push(TemplateInfo {
+            target: target.to_string(),
+            name: "default".to_string(),
+            file: defaults[target].to_string(),
+        });
+        let target_dir = Path::new(TEMPLATE_DIR).join(target);
+        let mut paths: Vec<_> = match fs::read_dir(&target_dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.is_file())
+                .collect(),
+            Err(_) => continue,
+        };
+        paths.sort();
+        for path in paths {
+            if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                templates.push(TemplateInfo {
+                    target: target.to_string(),
+                    name: name.to_string(),
+                    file: path.to_string_lossy().replace('\\', "/"),
+                });
+            }
+        }
+    }
+    templates
+}
+
+/// 根据转换目标和模板名称找到模板文件，名称为空或default就使用默认模板，
+/// 找不到命名模板时返回404错误
+pub fn find_template_file(
+    target: &str,
+    name: &str,
+    defaults: &HashMap<&str, &str>,
+) -> Result<String, AppError> {
+    if name.is_empty() || name == "default" {
+        return Ok(defaults
+            .get(target)
+            .copied()
+            .unwrap_or_default()
+            .to_string());
+    }
+    // 模板名称只能是字母、数字、-、_、.，防止读取模板目录以外的文件
+    let valid_name = !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || ['-', '_', '.'].contains(&c));
+    let found = match valid_name {
+        true => list_templates(defaults)
+            .into_iter()
+            .find(|t| t.target == target && t.name == name && t.name != "default"),
+        false => None,
+    };
+    match found {
+        Some(template) => Ok(template.file),
+        None => Err(AppError::NotFound(format!(
+            "没有找到{}的模板{}，可以访问/templates查看可用的模板",
+            target, name
+        ))),
+    }
+}
+
+/// 读取模板：template=<name>使用模板目录中的模板（可以是叠加模板），template=https://...使用远程模板，
+/// 远程模板的主机不允许使用返回403错误，获取失败或者格式无效返回502错误；模板文件读取失败就返回500错误
+pub fn load_template(
+    target: &str,
+    name: &str,
+    defaults: &HashMap<&str, &str>,
+    server_config: &ServerConfig,
+) -> Result<Template, AppError> {
+    let lower_name = name.to_lowercase();
+    if lower_name.starts_with("https://") || lower_name.starts_with("http://") {
+        return Ok(Template {
+            content: load_remote_template(target, name, server_config)?,
+            overrides: Vec::new(),
+        });
+    }
+    let template_file = find_template_file(target, name, defaults)?;
+    let content = read_template_file(target, &template_file)?;
+    if name.is_empty() || name == "default" {
+        return Ok(Template {
+            content,
+            overrides: Vec::new(),
+        });
+    }
+
+    // 叠加模板：读取它的基础模板，基础模板不能再是叠加模板
+    let Overlay { base, overrides } = match parse_overlay(&content) {
+        None => {
+            return Ok(Template {
+                content,
+                overrides: Vec::new(),
+            })
+        }
+        Some(overlay) => overlay.map_err(|reason| {
+            AppError::Internal(format!("叠加模板{}无效：{}", template_file, reason))
+        })?,
+    };
+    let base_file = find_template_file(target, &base, defaults).map_err(|_| {
+        AppError::Internal(format!("叠加模板{}的基础模板{}不存在", template_file, base))
+    })?;
+    let content = read_template_file(target, &base_file)?;
+    if parse_overlay(&content).is_some() {
+        return Err(AppError::Internal(format!(
+            "叠加模板{}的基础模板{}也是叠加模板",
+            template_file, base
+        )));
+    }
+    Ok(Template { content, overrides })
+}
+
+fn read_template_file(target: &str, template_file: &str) -> Result<String, AppError> {
+    fs::read_to_string(template_file).map_err(|e| {
+        tracing::error!("读取模板文件{}失败：{}", template_file, e);
+        AppError::Internal(format!("{}的模板文件读取失败", target))
+    })
+}
+
+struct Overlay {
+    base: String,
+    overrides: Vec<(String, String)>,
+}
+
+/// 叠加模板：只写base（基础模板的名称）和overrides（跟订阅链接中的覆盖参数一样）的YAML或JSON，例如
+/// `base: default`、`overrides: { tun: true }`，不是叠加模板就返回None
+fn parse_overlay(content: &str) -> Option<Result<Overlay, String>> {
+    let value: serde_yaml::Value = serde_yaml::from_str(content).ok()?;
+    let overlay = value.as_mapping()?;
+    let base = overlay.get("base")?;
+    Some(parse_overlay_fields(base, overlay.get("overrides")))
+}
+
+fn parse_overlay_fields(
+    base: &serde_yaml::Value,
+    items: Option<&serde_yaml::Value>,
+) -> Result<Overlay, String> {
+    let base = base.as_str().ok_or("base需要是模板名称")?.to_string();
+    let items = match items {
+        Some(items) => items.as_mapping().ok_or("overrides需要是键值对")?.clone(),
+        None => serde_yaml::Mapping::new(),
+    };
+    let mut parsed = Vec::new();
+    for (key, value) in &items {
+        let key = key.as_str().ok_or("overrides的名称需要是字符串")?;
+        // dns可以写成数组，其它的值都是字符串、数字或布尔值
+        let value = match value {
+            serde_yaml::Value::Sequence(seq) => seq
+                .iter()
+                .filter_map(|v| v.as_str())
+                .collect::<Vec<&str>>()
+                .join(","),
+            serde_yaml::Value::String(s) => s.clone(),
+            serde_yaml::Value::Bool(b) => b.to_string(),
+            serde_yaml::Value::Number(n) => n.to_string(),
+            _ => return Err(format!("{}的值无效", key)),
+        };
+        parsed.push(overrides::parse_override(key, &value).map_err(|e| e.to_string())?);
+    }
+    Ok(Overlay {
+        base,
+        overrides: parsed,
+    })
+}
+
+/// 获取远程模板，缓存没有过期就使用缓存，获取失败时，有过期的缓存也继续使用
+fn load_remote_template(
+    target: &str,
+    url: &str,
+    server_config: &ServerConfig,
+) -> Result<String, AppError> {
+    check_allowed_url(
+        url,
+        &server_config.template_hosts,
+        "remote_templates.allowed_hosts",
+    )
+    .map_err(|reason| AppError::Forbidden(format!("不允许使用这个远程模板：{}", reason)))?;
+    let bad_template = |reason: String| AppError::BadGateway(format!("远程模板不可用：{}", reason));
+
+    let ttl = Duration::from_secs(server_config.template_cache_ttl);
+    let cached = REMOTE_TEMPLATE_CACHE
+        .lock()
+        .ok()
+        .and_then(|cache| cache.get(url).cloned());
+    if let Some((fetched_at, content)) = &cached {
+        if fetched_at.elapsed() < ttl {
+            validate_template(target, content).map_err(bad_template)?;
+            metrics::record_template_cache("hit");
+            return Ok(content.clone());
+        }
+    }
+
+    metrics::record_template_cache("miss");
+    match fetch_remote_template(url, server_config) {
+        Ok(content) => {
+            validate_template(target, &content).map_err(bad_template)?;
+            cache_remote_template(url, &content, ttl);
+            Ok(content)
+        }
+        Err(reason) => match cached {
+            Some((_, content)) if validate_template(target, &content).is_ok() => {
+                metrics::record_fetch_error("template");
+                metrics::record_template_cache("stale");
+                tracing::warn!(
+                    "远程模板{}获取失败：{}，继续使用过期的缓存",
+                    access_log::redact_url(url),
+                    reason
+                );
+                Ok(content)
+            }
+            _ => {
+                metrics::record_fetch_error("template");
+                Err(bad_template(reason))
+            }
+        },
+    }
+}
+
+// 缓存最多保存的远程模板数，满了先删除过期的，还是满的就删除最早获取的
+const MAX_CACHED_TEMPLATES: usize = 32;
+
+fn cache_remote_template(url: &str, content: &str, ttl: Duration) {
+    let mut cache = match REMOTE_TEMPLATE_CACHE.lock() {
+        Ok(cache) => cache,
+        Err(_) => return,
+    };
+    if !cache.contains_key(url) && cache.len() >= MAX_CACHED_TEMPLATES {
+        cache.retain(|_, (fetched_at, _)| fetched_at.elapsed() < ttl);
+        if cache.len() >= MAX_CACHED_TEMPLATES {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, (fetched_at, _))| *fetched_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+    }
+    cache.insert(url.to_string(), (Instant::now(), content.to_string()));
+}
+
+lazy_static! {
+    // 获取远程模板共用的运行时（一个工作线程）和客户端，客户端不自动重定向，
+    // 重定向的地址在fetch_remote_template中检查是否为允许的主机
+    static ref FETCH_RUNTIME: Option<tokio::runtime::Runtime> =
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("template-fetch")
+            .enable_all()
+            .build()
+            .ok();
+    static ref FETCH_CLIENT: Option<reqwest::Client> = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .ok();
+}
+
+// 同步函数中使用异步：在共用的运行时中获取模板，当前线程等待结果
+fn fetch_remote_template(url: &str, server_config: &ServerConfig) -> Result<String, String> {
+    let (runtime, client) = match (FETCH_RUNTIME.as_ref(), FETCH_CLIENT.as_ref()) {
+        (Some(runtime), Some(client)) => (runtime, client.clone()),
+        _ => return Err("无法创建获取模板的客户端".to_string()),
+    };
+    let url = url.to_string();
+    let max_size = server_config.template_max_size;
+    let allowed_hosts = server_config.template_hosts.clone();
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    runtime.spawn(async move {
+        let result = fetch_with_redirects(&client, url, &allowed_hosts, max_size).await;
+        let _ = sender.send(result);
+    });
+    receiver
+        .recv()
+        .unwrap_or(Err("获取模板的任务异常退出".to_string()))
+}
+
+// 最多重定向5次，重定向后的地址也必须在允许的主机列表中
+async fn fetch_with_redirects(
+    client: &reqwest::Client,
+    mut url: String,
+    allowed_hosts: &[String],
+    max_size: usize,
+) -> Result<String, String> {
+    let mut redirects = 0;
+    let mut response = loop {
+        let response = client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| e.without_url().to_string())?;
+        if !response.status().is_redirection() {
+            break response;
+        }
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|location| response.url().join(location).ok())
+            .ok_or("重定向的地址无效")?;
+        redirects += 1;
+        if redirects > 5 {
+            return Err("重定向次数过多".to_string());
+        }
+        check_allowed_url(location.as_str(), allowed_hosts, "")
+            .map_err(|_| "重定向到了不允许的主机".to_string())?;
+        url = location.to_string();
+    };
+    if !response.status().is_success() {
+        return Err(format!("HTTP状态码{}", response.status()));
+    }
+    let mut body: Vec<u8> = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| e.without_url().to_string())?
+    {
+        body.extend_from_slice(&chunk);
+        if body.len() > max_size {
+            return Err(format!("模板超过{}字节", max_size));
+        }
+    }
+    String::from_utf8(body).map_err(|_| "模板不是UTF-8编码的文本".to_string())
+}
+
+/// 检查模板的格式：clash为YAML，singbox、xray为JSON对象，其它为非空的文本
+fn validate_template(target: &str, content: &str) -> Result<(), String> {
+    let valid = match target {
+        "clash" => serde_yaml::from_str::<serde_yaml::Value>(content).is_ok_and(|v| v.is_mapping()),
+        "singbox" | "xray" => {
+            serde_json::from_str::<serde_json::Value>(content).is_ok_and(|v| v.is_object())
+        }
+        _ => !content.trim().is_empty(),
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(format!("不是有效的{}模板", target)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    fn defaults() -> HashMap<&'static str, &'static str> {
+        HashMap::from([
+            ("clash", "template/clash.yaml"),
+            ("singbox", "template/sing-box.json"),
+        ])
+    }
+
+    #[test]
+    fn overlay_template_uses_base_with_overrides() {
+        let template =
+            load_template("clash", "tun", &defaults(), &ServerConfig::default()).unwrap();
+        let default = fs::read_to_string("template/clash.yaml").unwrap();
+        assert_eq!(template.content, default);
+        assert_eq!(
+            template.overrides,
+            [("tun".to_string(), "true".to_string())]
+        );
+    }
+
+    #[test]
+    fn templates_are_listed_per_target() {
+        let listed: Vec<(String, String, String)> = list_templates(&defaults())
+            .into_iter()
+            .map(|t| (t.target, t.name, t.file))
+            .collect();
+        let expected = [
+            ("clash", "default", "template/clash.yaml"),
+            ("clash", "tun", "template/clash/tun.yaml"),
+            ("singbox", "default", "template/sing-box.json"),
+            ("singbox", "desktop", "template/singbox/desktop.json"),
+        ];
+        let expected: Vec<(String, String, String)> = expected
+            .iter()
+            .map(|(target, name, file)| (target.to_string(), name.to_string(), file.to_string()))
+            .collect();
+        assert_eq!(listed, expected);
+    }
+
+    #[test]
+    fn template_names_are_validated() {
+        let find = |target: &str, name: &str| find_template_file(target, name, &defaults());
+        assert_eq!(find("clash", "").unwrap(), "template/clash.yaml");
+        assert_eq!(find("clash", "default").unwrap(), "template/clash.yaml");
+        assert_eq!(find("clash", "tun").unwrap(), "template/clash/tun.yaml");
+        assert_eq!(
+            find("singbox", "desktop").unwrap(),
+            "template/singbox/desktop.json"
+        );
+        // 命名模板只属于自己的转换目标，名称不能含有路径
+        for (target, name) in [
+            ("singbox", "tun"),
+            ("clash", "desktop"),
+            ("clash", "tun.yaml"),
+            ("clash", "../clash"),
+            ("clash", "clash/tun"),
+            ("clash", ".tun"),
+        ] {
+            assert!(
+                matches!(find(target, name), Err(AppError::NotFound(_))),
+                "{} {}",
+                target,
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_template_name_is_not_found() {
+        for name in ["missing", "../clash", ".hidden"] {
+            let result = load_template("clash", name, &defaults(), &ServerConfig::default());
+            assert!(matches!(result, Err(AppError::NotFound(_))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn remote_template_host_must_be_allowed() {
+        let result = load_template(
+            "clash",
+            "https://example.com/clash.yaml",
+            &defaults(),
+            &ServerConfig::default(),
+        );
+        assert!(matches!(result, Err(AppError::Forbidden(_))));
+    }
+
+    // 远程模板的缓存是全局的，用到缓存的测试不能同时运行
+    static CACHE_LOCK: Mutex<()> = Mutex::new(());
+
+    // 本地的模拟服务器，返回(端口, 每个路径的请求次数)：
+    // /ok、/counted返回有效的clash模板，/flaky只有第一次成功，/big返回100字节，
+    // /redirect-ok重定向到/ok，/redirect-away重定向到不允许的主机localhost
+    fn stub_server() -> (u16, Arc<Mutex<HashMap<String, usize>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let hits: Arc<Mutex<HashMap<String, usize>>> = Arc::default();
+        let counter = hits.clone();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().filter_map(Result::ok) {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+                    header.clear();
+                }
+                let path = request_line
+                    .split(' ')
+                    .nth(1)
+                    .unwrap_or_default()
+                    .to_string();
+                let count = {
+                    let mut hits = counter.lock().unwrap();
+                    let count = hits.entry(path.clone()).or_insert(0);
+                    *count += 1;
+                    *count
+                };
+                let (status, location, body) = match path.as_str() {
+                    "/ok" | "/counted" => {
+                        ("200 OK", String::new(), "mixed-port: 7890\n".to_string())
+                    }
+                    "/flaky" if count == 1 => ("200 OK", String::new(), "port: 1\n".to_string()),
+                    "/big" => ("200 OK", String::new(), format!("a: {}\n", "x".repeat(97))),
+                    "/redirect-ok" => ("302 Found", "/ok".to_string(), String::new()),
+                    "/redirect-away" => (
+                        "302 Found",
+                        format!("http://localhost:{}/ok", port),
+                        String::new(),
+                    ),
+                    _ => ("500 Internal Server Error", String::new(), String::new()),
+                };
+                let location = match location.is_empty() {
+                    true => String::new(),
+                    false => format!("Location: {}\r\n", location),
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    location,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (port, hits)
+    }
+
+    fn stub_config(port: u16, ttl: u64) -> ServerConfig {
+        ServerConfig {
+            template_hosts: vec![format!("http://127.0.0.1:{}", port)],
+            template_cache_ttl: ttl,
+            template_max_size: 64,
+            ..Default::default()
+        }
+    }
+
+    fn load_stub(port: u16, path: &str, config: &ServerConfig) -> Result<String, AppError> {
+        let url = format!("http://127.0.0.1:{}{}", port, path);
+        load_template("clash", &url, &defaults(), config).map(|template| template.content)
+    }
+
+    #[test]
+    fn remote_template_is_fetched_and_cached() {
+        let _lock = CACHE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let (port, hits) = stub_server();
+        let config = stub_config(port, 600);
+        for _ in 0..2 {
+            assert_eq!(
+                load_stub(port, "/counted", &config).unwrap(),
+                "mixed-port: 7890\n"
+            );
+        }
+        assert_eq!(hits.lock().unwrap()["/counted"], 1);
+        assert_eq!(
+            load_stub(port, "/redirect-ok", &config).unwrap(),
+            "mixed-port: 7890\n"
+        );
+    }
+
+    #[test]
+    fn stale_remote_template_is_used_when_upstream_fails() {
+        let _lock = CACHE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let (port, hits) = stub_server();
+        let config = stub_config(port, 0);
+        assert_eq!(load_stub(port, "/flaky", &config).unwrap(), "port: 1\n");
+        assert_eq!(load_stub(port, "/flaky", &config).unwrap(), "port: 1\n");
+        assert_eq!(hits.lock().unwrap()["/flaky"], 2);
+        let result = load_stub(port, "/missing", &config);
+        assert!(matches!(result, Err(AppError::BadGateway(_))));
+    }
+
+    #[test]
+    fn remote_template_redirects_and_size_are_checked() {
+        let (port, hits) = stub_server();
+        let config = stub_config(port, 600);
+        let result = load_stub(port, "/redirect-away", &config);
+        assert!(
+            matches!(&result, Err(AppError::BadGateway(m)) if m.contains("不允许的主机")),
+            "{:?}",
+            result
+        );
+        assert!(!hits.lock().unwrap().contains_key("/ok"));
+        let result = load_stub(port, "/big", &config);
+        assert!(
+            matches!(&result, Err(AppError::BadGateway(m)) if m.contains("超过64字节")),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn remote_template_cache_is_bounded() {
+        let _lock = CACHE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let ttl = Duration::from_secs(600);
+        for index in 0..MAX_CACHED_TEMPLATES + 5 {
+            cache_remote_template(&format!("https://example.com/{}.yaml", index), "a: 1", ttl);
+        }
+        let cache = REMOTE_TEMPLATE_CACHE.lock().unwrap();
+        assert_eq!(cache.len(), MAX_CACHED_TEMPLATES);
+        assert!(cache.contains_key(&format!(
+            "https://example.com/{}.yaml",
+            MAX_CACHED_TEMPLATES + 4
+        )));
+    }
+
+    #[test]
+    fn overlay_fields_are_validated() {
+        let overlay = parse_overlay("base: default\noverrides: { dns: [1.1.1.1, 8.8.8.8] }")
+            .unwrap()
+            .unwrap();
+        assert_eq!(overlay.base, "default");
+        assert_eq!(
+            overlay.overrides,
+            [("dns".to_string(), "1.1.1.1,8.8.8.8".to_string())]
+        );
+        assert!(parse_overlay("base: default\noverrides: { tun: maybe }")
+            .unwrap()
+            .is_err());
+        assert!(parse_overlay("mixed-port: 7890\nproxies: []").is_none());
+    }
+}
@@ -0,0 +1,248 @@
+use super::config::{get_yaml_value, get_yaml_value_with_fallback};
+use serde_yaml::Value as YamlValue;
+
+// 节点没有设置uTLS指纹时使用的默认值
+const DEFAULT_FINGERPRINT: &str = "chrome";
+
+// 可以作为uTLS指纹的名称（fingerprint字段的值不是这些时，视为证书指纹）
+const UTLS_FINGERPRINTS: [&str; 10] = [
+    "chrome",
+    "firefox",
+    "safari",
+    "ios",
+    "android",
+    "edge",
+    "360",
+    "qq",
+    "random",
+    "randomized",
+];
+
+/// TLS分片设置（packets：分片的数据包，length：分片长度，interval：分片间隔）
+#[derive(Default, Clone)]
+pub struct TlsFragment {
+    pub packets: String,
+    pub length: String,
+    pub interval: String,
+}
+
+/// 从节点配置中提取的TLS设置，各个转换目标共用
+#[derive(Default, Clone)]
+pub struct TlsOptions {
+    pub enabled: bool,
+    pub server_name: String,
+    pub alpn: Vec<String>,
+    pub skip_cert_verify: bool,
+    pub fingerprint: String,
+    pub ech_config: String,
+    pub fragment: Option<TlsFragment>,
+}
+
+impl TlsOptions {
+    /// 读取节点的TLS设置，vless/trojan读取`tls`字段，ss读取`plugin-opts.tls`字段，
+    /// 节点没有设置tls字段时，ss默认开启，其它节点才根据host是否为workers.dev判断
+    pub fn from_yaml(yaml_value: &YamlValue, node_type: &str) -> Self {
+        let opts_prefix: &[&str] = match node_type {
+            "ss" => &["plugin-opts"],
+            _ => &[],
+        };
+        let get_value = |key: &str| -> Option<&YamlValue> {
+            let keys: Vec<&str> = opts_prefix.iter().copied().chain([key]).collect();
+            get_yaml_value(yaml_value, &keys)
+        };
+
+        let host = match node_type {
+            "ss" => get_yaml_value(yaml_value, &["plugin-opts", "host"]),
+            _ => get_yaml_value(yaml_value, &["ws-opts", "headers", "Host"]),
+        }
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+
+        let enabled = get_value("tls")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(node_type == "ss" || !host.ends_with("workers.dev"));
+
+        let server_name = match node_type {
+            "ss" => get_value("servername").and_then(|v| v.as_str()),
+            _ => get_yaml_value_with_fallback(yaml_value, &["sni", "servername"]),
+        }
+        .unwrap_or_default()
+        .to_string();
+
+        // alpn支持数组写法，也支持"h2,http/1.1"这种逗号分隔的写法
+        let alpn = match get_value("alpn") {
+            Some(YamlValue::Sequence(seq)) => seq
+                .iter()
+                .filter_map(|v| v.as_str())
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            Some(YamlValue::String(s)) => s
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let skip_cert_verify = get_value("skip-cert-verify")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+
+        // uTLS指纹：优先使用client-fingerprint字段；fingerprint字段是uTLS指纹名称时（旧的写法）也可以使用，
+        // 是证书指纹（十六进制）时不使用；都没有就使用chrome，跟sing-box节点的默认值一致
+        let fingerprint = get_value("client-fingerprint")
+            .and_then(|v| v.as_str())
+            .or_else(|| {
+                get_value("fingerprint")
+                    .and_then(|v| v.as_str())
+                    .filter(|fp| UTLS_FINGERPRINTS.contains(&fp.trim().to_lowercase().as_str()))
+            })
+            .map_or(DEFAULT_FINGERPRINT.to_string(), |fp| {
+                fp.trim().to_lowercase()
+            });
+
+        // ECH配置，跟clash.meta/mihomo的写法一致：ech-opts: { enable: true, config: base64 }
+        let ech_config = match get_value("ech-opts") {
+            Some(ech)
+                if get_yaml_value(ech, &["enable"]).and_then(|v| v.as_bool()) != Some(false) =>
+            {
+                get_yaml_value(ech, &["config"])
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            }
+            _ => String::new(),
+        };
+
+        // TLS分片：tls-fragment: { enable: true, packets: tlshello, length: 100-200, interval: 10-20 }
+        let fragment = get_value("tls-fragment")
+            .filter(|frag| {
+                get_yaml_value(frag, &["enable"]).and_then(|v| v.as_bool()) != Some(false)
+            })
+            .map(|frag| {
+                let get_str = |key: &str, default: &str| {
+                    get_yaml_value(frag, &[key])
+                        .map(|v| match v {
+                            YamlValue::Number(n) => n.to_string(),
+                            _ => v.as_str().unwrap_or(default).to_string(),
+                        })
+                        .unwrap_or(default.to_string())
+                };
+                TlsFragment {
+                    packets: get_str("packets", "tlshello"),
+                    length: get_str("length", "100-200"),
+                    interval: get_str("interval", "10-20"),
+                }
+            });
+
+        TlsOptions {
+            enabled,
+            server_name,
+            alpn,
+            skip_cert_verify,
+            fingerprint,
+            ech_config,
+            fragment,
+        }
+    }
+
+    /// sing-box的ech.config需要PEM格式的多行字符串数组
+    pub fn ech_pem_lines(&self) -> Vec<String> {
+        match self.ech_config.trim() {
+            "" => Vec::new(),
+            config if config.starts_with("-----BEGIN") => {
+                config.lines().map(|line| line.trim().to_string()).collect()
+            }
+            config => vec![
+                "-----BEGIN ECH CONFIGS-----".to_string(),
+                config.to_string(),
+                "-----END ECH CONFIGS-----".to_string(),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tls_options(yaml: &str, node_type: &str) -> TlsOptions {
+        TlsOptions::from_yaml(&serde_yaml::from_str(yaml).unwrap(), node_type)
+    }
+
+    #[test]
+    fn tls_defaults_follow_node_type_and_host() {
+        let ss = tls_options("plugin-opts: { host: a.workers.dev }", "ss");
+        assert!(ss.enabled);
+        let ss = tls_options("plugin-opts: { host: a.workers.dev, tls: false }", "ss");
+        assert!(!ss.enabled);
+        let vless = tls_options("ws-opts: { headers: { Host: a.workers.dev } }", "vless");
+        assert!(!vless.enabled);
+        let vless = tls_options("ws-opts: { headers: { Host: a.example.com } }", "vless");
+        assert!(vless.enabled);
+        let vless = tls_options(
+            "tls: true\nws-opts: { headers: { Host: a.workers.dev } }",
+            "vless",
+        );
+        assert!(vless.enabled);
+    }
+
+    #[test]
+    fn tls_fields_are_read_from_node() {
+        let options = tls_options(
+            "{ sni: a.example.com, alpn: 'h2, http/1.1', skip-cert-verify: false, \
+             client-fingerprint: firefox, tls-fragment: { length: 50-100 } }",
+            "vless",
+        );
+        assert_eq!(options.server_name, "a.example.com");
+        assert_eq!(options.alpn, ["h2", "http/1.1"]);
+        assert!(!options.skip_cert_verify);
+        assert_eq!(options.fingerprint, "firefox");
+        let fragment = options.fragment.unwrap();
+        assert_eq!(
+            (fragment.packets, fragment.length, fragment.interval),
+            ("tlshello".into(), "50-100".into(), "10-20".into())
+        );
+
+        let ss = tls_options(
+            "plugin-opts: { servername: b.example.com, alpn: [h2] }\nsni: ignored",
+            "ss",
+        );
+        assert_eq!(ss.server_name, "b.example.com");
+        assert_eq!(ss.alpn, ["h2"]);
+    }
+
+    #[test]
+    fn fingerprint_falls_back_to_utls_name_or_default() {
+        let options = tls_options("fingerprint: Firefox", "trojan");
+        assert_eq!(options.fingerprint, "firefox");
+        let options = tls_options("{ client-fingerprint: safari, fingerprint: ios }", "vless");
+        assert_eq!(options.fingerprint, "safari");
+        // 证书指纹不是uTLS指纹，使用默认值
+        let options = tls_options("fingerprint: 6b5f0c0d", "trojan");
+        assert_eq!(options.fingerprint, "chrome");
+        let options = tls_options("{}", "vless");
+        assert_eq!(options.fingerprint, "chrome");
+    }
+
+    #[test]
+    fn disabled_fragment_and_ech_are_ignored() {
+        let options = tls_options(
+            "{ tls-fragment: { enable: false }, ech-opts: { enable: false, config: abc } }",
+            "vless",
+        );
+        assert!(options.fragment.is_none());
+        assert!(options.ech_pem_lines().is_empty());
+
+        let options = tls_options("ech-opts: { config: abc }", "vless");
+        assert_eq!(
+            options.ech_pem_lines(),
+            [
+                "-----BEGIN ECH CONFIGS-----",
+                "abc",
+                "-----END ECH CONFIGS-----"
+            ]
+        );
+    }
+}
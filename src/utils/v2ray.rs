@@ -1,4 +1,4 @@
-use super::config::{get_yaml_value, get_yaml_value_with_fallback};
+use super::{config::get_yaml_value, tls::TlsOptions};
 use serde_qs as qs;
 use serde_yaml::Value as YamlValue;
 use std::collections::BTreeMap;
@@ -25,9 +25,14 @@ pub fn build_v2ray_links(
             let ss_link = build_ss_link(yaml_value, remarks.clone(), server_address, server_port);
             return (remarks, ss_link); // 前面是节点名称，后面是节点配置
         }
+        "vmess" => {
+            let vmess_link =
+                build_vmess_link(yaml_value, remarks.clone(), server_address, server_port);
+            return (remarks, vmess_link); // 前面是节点名称，后面是节点配置
+        }
         _ => {}
     }
-    return ("".to_string(), "".to_string());
+    ("".to_string(), "".to_string())
 }
 
 fn build_ss_link(
@@ -36,23 +41,21 @@ fn build_ss_link(
     server_address: String,
     server_port: u16,
 ) -> String {
-    let path = get_yaml_value(&yaml_value, &["plugin-opts", "path"])
+    let path = get_yaml_value(yaml_value, &["plugin-opts", "path"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let host = get_yaml_value(&yaml_value, &["plugin-opts", "host"])
+    let host = get_yaml_value(yaml_value, &["plugin-opts", "host"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let tls_val = get_yaml_value(&yaml_value, &["plugin-opts", "tls"])
-        .and_then(|v| v.as_bool())
-        .unwrap_or(true);
-    let password = get_yaml_value(&yaml_value, &["password"])
+    let tls = TlsOptions::from_yaml(yaml_value, "ss");
+    let password = get_yaml_value(yaml_value, &["password"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
     let base64_encoded = base64::encode(format!("none:{}", password).as_bytes());
 
-    let insert_tls_str = match tls_val {
-        true => format!("tls;"),
-        false => format!(""),
+    let insert_tls_str = match tls.enabled {
+        true => "tls;".to_string(),
+        false => String::new(),
     };
 
     let plugin = format!(
@@ -74,39 +77,30 @@ fn build_vless_link(
     server_address: String,
     server_port: u16,
 ) -> String {
-    let uuid = get_yaml_value(&yaml_value, &["uuid"])
-        .and_then(|v| v.as_str())
-        .unwrap_or_default();
-    let network = get_yaml_value(&yaml_value, &["network"])
+    let uuid = get_yaml_value(yaml_value, &["uuid"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let client_fingerprint = get_yaml_value(&yaml_value, &["client-fingerprint"])
+    let network = get_yaml_value(yaml_value, &["network"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let path = get_yaml_value(&yaml_value, &["ws-opts", "path"])
+    let path = get_yaml_value(yaml_value, &["ws-opts", "path"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let host = get_yaml_value(&yaml_value, &["ws-opts", "headers", "Host"])
+    let host = get_yaml_value(yaml_value, &["ws-opts", "headers", "Host"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
 
-    let sni = get_yaml_value_with_fallback(&yaml_value, &["sni", "servername"]).unwrap_or_default();
-    let security = match host.ends_with("workers.dev") {
-        true => "none",
-        false => "tls",
-    };
+    // TLS由节点的tls字段决定，没有设置时才根据workers.dev判断
+    let tls = TlsOptions::from_yaml(yaml_value, "vless");
 
     let encoding_remarks = urlencoding::encode(remarks.as_str());
 
     let mut params = BTreeMap::new();
-    params.insert("encryption", "none");
-    params.insert("security", &security);
-    params.insert("type", &network);
-    params.insert("host", &host);
-    params.insert("sni", &sni);
-    params.insert("fp", &client_fingerprint);
-    params.insert("allowInsecure", "1");
-    params.insert("path", &path);
+    params.insert("encryption", "none".to_string());
+    params.insert("type", network.to_string());
+    params.insert("host", host.to_string());
+    params.insert("path", path.to_string());
+    insert_tls_params(&mut params, &tls);
 
     // 过滤掉值为空的键值对，然后将数据结构序列化为Query String格式的字符串
     let all_params_str = serialize_to_query_string(params);
@@ -123,39 +117,30 @@ fn build_trojan_linnk(
     server_address: String,
     server_port: u16,
 ) -> String {
-    let password = get_yaml_value(&yaml_value, &["password"])
+    let password = get_yaml_value(yaml_value, &["password"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let network = get_yaml_value(&yaml_value, &["network"])
+    let network = get_yaml_value(yaml_value, &["network"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let client_fingerprint = get_yaml_value(&yaml_value, &["client-fingerprint"])
+    let path = get_yaml_value(yaml_value, &["ws-opts", "path"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
-    let path = get_yaml_value(&yaml_value, &["ws-opts", "path"])
-        .and_then(|v| v.as_str())
-        .unwrap_or_default();
-    let host = get_yaml_value(&yaml_value, &["ws-opts", "headers", "Host"])
+    let host = get_yaml_value(yaml_value, &["ws-opts", "headers", "Host"])
         .and_then(|v| v.as_str())
         .unwrap_or_default();
 
-    let sni = get_yaml_value_with_fallback(&yaml_value, &["sni", "servername"]).unwrap_or_default();
-    let security = match host.ends_with("workers.dev") {
-        true => "none",
-        false => "tls",
-    };
+    // TLS由节点的tls字段决定，没有设置时才根据workers.dev判断
+    let tls = TlsOptions::from_yaml(yaml_value, "trojan");
 
     let encoding_remarks = urlencoding::encode(&remarks);
 
     // 构建节点链接后面的参数
     let mut params = BTreeMap::new();
-    params.insert("security", security);
-    params.insert("sni", &sni);
-    params.insert("fp", &client_fingerprint);
-    params.insert("type", &network);
-    params.insert("host", &host);
-    params.insert("allowInsecure", "1");
-    params.insert("path", &path);
+    params.insert("type", network.to_string());
+    params.insert("host", host.to_string());
+    params.insert("path", path.to_string());
+    insert_tls_params(&mut params, &tls);
 
     // 过滤掉值为空的键值对，然后将数据结构序列化为Query String格式的字符串
     let all_params_str = serialize_to_query_string(params);
@@ -166,9 +151,91 @@ fn build_trojan_linnk(
     trojan_link
 }
 
-fn serialize_to_query_string(params: BTreeMap<&str, &str>) -> String {
+fn build_vmess_link(
+    yaml_value: &mut YamlValue,
+    remarks: String,
+    server_address: String,
+    server_port: u16,
+) -> String {
+    let uuid = get_yaml_value(yaml_value, &["uuid"])
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let alter_id = get_yaml_value(yaml_value, &["alterId"])
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0);
+    let cipher = get_yaml_value(yaml_value, &["cipher"])
+        .and_then(|v| v.as_str())
+        .unwrap_or("auto");
+    let network = get_yaml_value(yaml_value, &["network"])
+        .and_then(|v| v.as_str())
+        .unwrap_or("ws");
+    let path = get_yaml_value(yaml_value, &["ws-opts", "path"])
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let host = get_yaml_value(yaml_value, &["ws-opts", "headers", "Host"])
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+
+    // TLS由节点的tls字段决定，没有设置时才根据workers.dev判断
+    let tls = TlsOptions::from_yaml(yaml_value, "vmess");
+
+    // vmess分享链接是base64编码的JSON（v2rayN的格式）
+    let vmess_json = serde_json::json!({
+        "v": "2",
+        "ps": remarks,
+        "add": server_address,
+        "port": server_port.to_string(),
+        "id": uuid,
+        "aid": alter_id.to_string(),
+        "scy": cipher,
+        "net": network,
+        "type": "none",
+        "host": host,
+        "path": path,
+        "tls": if tls.enabled { "tls" } else { "" },
+        "sni": if tls.enabled { tls.server_name.as_str() } else { "" },
+        "alpn": if tls.enabled { tls.alpn.join(",") } else { String::new() },
+        "fp": if tls.enabled { tls.fingerprint.as_str() } else { "" },
+    });
+
+    format!(
+        "vmess://{}",
+        base64::encode(vmess_json.to_string().as_bytes())
+    )
+}
+
+/// 将节点的TLS设置写入分享链接的参数中（security、sni、fp、alpn、allowInsecure、ech、fragment）
+fn insert_tls_params(params: &mut BTreeMap<&str, String>, tls: &TlsOptions) {
+    if !tls.enabled {
+        params.insert("security", "none".to_string());
+        return;
+    }
+    params.insert("security", "tls".to_string());
+    params.insert("sni", tls.server_name.clone());
+    params.insert("fp", tls.fingerprint.clone());
+    params.insert("alpn", tls.alpn.join(","));
+    params.insert(
+        "allowInsecure",
+        match tls.skip_cert_verify {
+            true => "1".to_string(),
+            false => "0".to_string(),
+        },
+    );
+    params.insert("ech", tls.ech_config.clone());
+    if let Some(fragment) = &tls.fragment {
+        // 格式：length,interval,packets（跟Hiddify、NekoBox的分享链接一致）
+        params.insert(
+            "fragment",
+            format!(
+                "{},{},{}",
+                fragment.length, fragment.interval, fragment.packets
+            ),
+        );
+    }
+}
+
+fn serialize_to_query_string(params: BTreeMap<&str, String>) -> String {
     let filtered_params: BTreeMap<_, _> =
         params.into_iter().filter(|(_, v)| !v.is_empty()).collect();
-    let all_params_str = qs::to_string(&filtered_params).unwrap_or_default();
-    all_params_str
+    qs::to_string(&filtered_params).unwrap_or_default()
 }
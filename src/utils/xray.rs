@@ -0,0 +1,251 @@
+use super::{config::get_yaml_value, tls::TlsOptions};
+use serde_json::{json, Value as JsonValue};
+use serde_yaml::Value as YamlValue;
+
+/// 检查节点能否转换为Xray的outbound，不能转换就返回原因
+pub fn check_xray_node(node_type: &str, yaml_value: &YamlValue) -> Result<(), String> {
+    let plugin = get_yaml_value(yaml_value, &["plugin"])
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    match (node_type, plugin) {
+        ("ss", "" | "v2ray-plugin") => Ok(()),
+        ("ss", _) => Err(format!("不支持ss的{}插件", plugin)),
+        _ => Ok(()),
+    }
+}
+
+/// 构建Xray/V2Ray的outbound，返回的前面是节点名称，后面是outbound的JSON字符串；
+/// 节点开启了TLS分片时，返回的是[outbound, 分片用的freedom outbound]数组
+pub fn build_xray_outbound(
+    proxy_type: &str,
+    yaml_value: &mut YamlValue,
+    remarks: String,
+    server_address: String,
+    server_port: u16,
+) -> (String, String) {
+    let get_str = |keys: &[&str]| -> String {
+        get_yaml_value(yaml_value, keys)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    // Xray的地址不需要IPv6的方括号
+    let address = server_address.trim_start_matches('[').trim_end_matches(']');
+
+    let settings = match proxy_type {
+        "vless" => json!({
+            "vnext": [{
+                "address": address,
+                "port": server_port,
+                "users": [{ "id": get_str(&["uuid"]), "encryption": "none" }]
+            }]
+        }),
+        "vmess" => {
+            let alter_id = get_yaml_value(yaml_value, &["alterId"])
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0);
+            let cipher = match get_str(&["cipher"]).as_str() {
+                "" => "auto".to_string(),
+                cipher => cipher.to_string(),
+            };
+            json!({
+                "vnext": [{
+                    "address": address,
+                    "port": server_port,
+                    "users": [{ "id": get_str(&["uuid"]), "alterId": alter_id, "security": cipher }]
+                }]
+            })
+        }
+        "trojan" => json!({
+            "servers": [{ "address": address, "port": server_port, "password": get_str(&["password"]) }]
+        }),
+        "ss" => {
+            // ss的v2ray-plugin（websocket模式、mux=0）相当于shadowsocks + ws传输
+            if check_xray_node(proxy_type, yaml_value).is_err() {
+                return (String::new(), String::new());
+            }
+            json!({
+                "servers": [{
+                    "address": address,
+                    "port": server_port,
+                    "method": get_str(&["cipher"]),
+                    "password": get_str(&["password"])
+                }]
+            })
+        }
+        _ => return (String::new(), String::new()),
+    };
+
+    // 传输层设置
+    let (network, host, path) = match proxy_type {
+        "ss" => match get_str(&["plugin"]).as_str() {
+            "v2ray-plugin" => (
+                "ws".to_string(),
+                get_str(&["plugin-opts", "host"]),
+                get_str(&["plugin-opts", "path"]),
+            ),
+            _ => ("tcp".to_string(), String::new(), String::new()),
+        },
+        _ => {
+            let network = match get_str(&["network"]).as_str() {
+                "" => "tcp".to_string(),
+                network => network.to_string(),
+            };
+            (
+                network,
+                get_str(&["ws-opts", "headers", "Host"]),
+                get_str(&["ws-opts", "path"]),
+            )
+        }
+    };
+    let mut stream_settings = json!({ "network": network, "security": "none" });
+    if network == "ws" {
+        stream_settings["wsSettings"] = json!({ "path": path, "headers": { "Host": host } });
+    }
+
+    let tls = TlsOptions::from_yaml(yaml_value, proxy_type);
+    let mut fragment_outbound = JsonValue::Null;
+    if tls.enabled {
+        let server_name = match tls.server_name.is_empty() {
+            true => host.clone(),
+            false => tls.server_name.clone(),
+        };
+        let mut tls_settings = json!({
+            "serverName": server_name,
+            "allowInsecure": tls.skip_cert_verify,
+        });
+        if !tls.fingerprint.is_empty() {
+            tls_settings["fingerprint"] = json!(tls.fingerprint);
+        }
+        if !tls.alpn.is_empty() {
+            tls_settings["alpn"] = json!(tls.alpn);
+        }
+        if !tls.ech_config.is_empty() {
+            tls_settings["echConfigList"] = json!(tls.ech_config);
+        }
+        stream_settings["security"] = json!("tls");
+        stream_settings["tlsSettings"] = tls_settings;
+
+        // TLS分片由freedom outbound完成，节点通过dialerProxy使用它
+        if let Some(fragment) = &tls.fragment {
+            let fragment_tag = format!(
+                "fragment:{}:{}:{}",
+                fragment.packets, fragment.length, fragment.interval
+            );
+            stream_settings["sockopt"] = json!({ "dialerProxy": fragment_tag });
+            fragment_outbound = json!({
+                "tag": fragment_tag,
+                "protocol": "freedom",
+                "settings": {
+                    "fragment": {
+                        "packets": fragment.packets,
+                        "length": fragment.length,
+                        "interval": fragment.interval
+                    }
+                }
+            });
+        }
+    }
+
+    let protocol = match proxy_type {
+        "ss" => "shadowsocks",
+        _ => proxy_type,
+    };
+    let outbound = json!({
+        "tag": remarks,
+        "protocol": protocol,
+        "settings": settings,
+        "streamSettings": stream_settings,
+    });
+
+    let json_value = match fragment_outbound.is_null() {
+        true => outbound,
+        false => json!([outbound, fragment_outbound]),
+    };
+    let json_string = serde_json::to_string_pretty(&json_value).unwrap_or_default();
+
+    (remarks, json_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(proxy_type: &str, yaml: &str) -> JsonValue {
+        let mut yaml_value: YamlValue = serde_yaml::from_str(yaml).unwrap();
+        let (name, outbound) = build_xray_outbound(
+            proxy_type,
+            &mut yaml_value,
+            "n1".to_string(),
+            "[2606:4700::1]".to_string(),
+            443,
+        );
+        match outbound.is_empty() {
+            true => JsonValue::Null,
+            false => {
+                assert_eq!(name, "n1");
+                serde_json::from_str(&outbound).unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_nodes_are_skipped() {
+        assert!(build("ss", "plugin: obfs").is_null());
+        assert!(build("hysteria2", "{}").is_null());
+    }
+
+    #[test]
+    fn vless_ws_tls_outbound() {
+        let outbound = build(
+            "vless",
+            "{ uuid: abc, network: ws, client-fingerprint: chrome, alpn: [h2], \
+             ws-opts: { path: /ws, headers: { Host: a.example.com } } }",
+        );
+        assert_eq!(outbound["tag"], "n1");
+        assert_eq!(outbound["protocol"], "vless");
+        let server = &outbound["settings"]["vnext"][0];
+        assert_eq!(server["address"], "2606:4700::1");
+        assert_eq!(server["port"], 443);
+        assert_eq!(server["users"][0]["id"], "abc");
+        let stream = &outbound["streamSettings"];
+        assert_eq!(stream["network"], "ws");
+        assert_eq!(stream["wsSettings"]["path"], "/ws");
+        assert_eq!(stream["wsSettings"]["headers"]["Host"], "a.example.com");
+        assert_eq!(stream["security"], "tls");
+        // 没有sni时使用ws的Host
+        assert_eq!(stream["tlsSettings"]["serverName"], "a.example.com");
+        assert_eq!(stream["tlsSettings"]["fingerprint"], "chrome");
+        assert_eq!(stream["tlsSettings"]["alpn"], json!(["h2"]));
+    }
+
+    #[test]
+    fn ss_v2ray_plugin_uses_ws_transport() {
+        let outbound = build(
+            "ss",
+            "{ cipher: aes-128-gcm, password: pw, plugin: v2ray-plugin, \
+             plugin-opts: { mode: websocket, host: a.example.com, path: /ss, tls: false } }",
+        );
+        assert_eq!(outbound["protocol"], "shadowsocks");
+        assert_eq!(outbound["settings"]["servers"][0]["method"], "aes-128-gcm");
+        assert_eq!(outbound["streamSettings"]["network"], "ws");
+        assert_eq!(outbound["streamSettings"]["wsSettings"]["path"], "/ss");
+        assert_eq!(outbound["streamSettings"]["security"], "none");
+    }
+
+    #[test]
+    fn tls_fragment_adds_freedom_outbound() {
+        let outbounds = build(
+            "trojan",
+            "{ password: pw, sni: a.example.com, tls-fragment: { length: 50-100 } }",
+        );
+        let tag = "fragment:tlshello:50-100:10-20";
+        assert_eq!(
+            outbounds[0]["streamSettings"]["sockopt"]["dialerProxy"],
+            tag
+        );
+        assert_eq!(outbounds[1]["tag"], tag);
+        assert_eq!(outbounds[1]["protocol"], "freedom");
+        assert_eq!(outbounds[1]["settings"]["fragment"]["length"], "50-100");
+    }
+}